@@ -0,0 +1,80 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use zip::result::ZipError;
+use zip::write::FileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+use crate::errors::ArchiveException;
+use crate::state::State;
+use crate::{load_csv_path, load_database_path, load_settings_path, load_state_path, Exception};
+
+/// The members of the archive and whether they are required on import.
+fn member_list() -> Result<Vec<(&'static str, PathBuf, bool)>, Exception> {
+    Ok(vec![
+        ("Settings.toml", load_settings_path()?, true),
+        ("state.json", load_state_path()?, true),
+        ("Papers.csv", load_csv_path()?, false),
+        ("papers.db", load_database_path()?, false),
+    ])
+}
+
+/// Bundles the settings, the saved state, the csv file and the database of
+/// "storage_backend = sqlite" into a zip archive at "path".
+pub fn export(path: &Path) -> Result<(), Exception> {
+    let mut zip = ZipWriter::new(File::create(path)?);
+    for (name, member_path, required) in member_list()? {
+        let body = match fs::read(&member_path) {
+            Ok(body) => body,
+            // Nothing has been searched yet.
+            Err(_) if name == "state.json" => serde_json::to_vec_pretty(&State::default())?,
+            Err(_) if !required => continue,
+            Err(e) => return Err(e.into()),
+        };
+        zip.start_file(name, FileOptions::default())?;
+        zip.write_all(&body)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+/// Restores the files bundled by [export]. The archive is validated before
+/// any file is overwritten.
+pub fn import(path: &Path) -> Result<(), Exception> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let member_list = member_list()?;
+
+    // Read every member first.
+    let mut body_list = Vec::new();
+    for (name, member_path, required) in member_list {
+        let mut body = Vec::new();
+        match archive.by_name(name) {
+            Ok(mut member) => {
+                member.read_to_end(&mut body)?;
+            }
+            Err(ZipError::FileNotFound) if !required => continue,
+            Err(ZipError::FileNotFound) => {
+                let message = format!("'{}' is missing in {}.", name, path.display());
+                return Err(Box::new(ArchiveException(message)));
+            }
+            Err(e) => return Err(e.into()),
+        }
+        body_list.push((name, member_path, body));
+    }
+
+    // The saved state must still be readable.
+    let (_, _, state) = body_list
+        .iter()
+        .find(|(name, _, _)| *name == "state.json")
+        .unwrap();
+    if let Err(e) = serde_json::from_slice::<State>(state) {
+        let message = format!("'state.json' in {} is not valid: {}", path.display(), e);
+        return Err(Box::new(ArchiveException(message)));
+    }
+
+    for (_, member_path, body) in body_list {
+        fs::write(member_path, body)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use std::path::PathBuf;
+
+use chrono::NaiveDate;
+use clap::Parser;
+
+/// Command line options. Without any, the crawler runs on its schedule.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// The settings file to use instead of "./Settings.toml".
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Search without sending emails or writing the csv file, as with
+    /// "dry_run = true" in the settings.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Check the settings file, without launching Chrome or sending any
+    /// email, and exit.
+    #[arg(long)]
+    pub validate_config: bool,
+
+    /// Send a test email with the configured profile, attaching the csv
+    /// file when there is one, print the response of the server and exit.
+    #[arg(long)]
+    pub test_email: bool,
+
+    /// Search once for papers published on or after this date (YYYY-MM-DD)
+    /// and print them as csv. Seen papers and the schedule are left as is.
+    #[arg(long, value_parser = parse_date)]
+    pub since: Option<NaiveDate>,
+
+    /// Search the keywords once, as on their schedule, and exit with 0 when
+    /// new papers were found, 2 when none were, 3 on a crawl error, 4 when
+    /// the email could not be sent and 5 on a bad setting.
+    #[arg(long, conflicts_with_all = ["since", "full_digest"])]
+    pub once: bool,
+
+    /// Search once and send every paper found, including the ones seen
+    /// before, as a full snapshot. Seen papers and the schedule are left as is.
+    #[arg(long, conflicts_with = "since")]
+    pub full_digest: bool,
+
+    /// A keyword to search for instead of the configured keywords.
+    /// Repeat it for more than one keyword.
+    #[arg(long)]
+    pub keyword: Vec<String>,
+
+    /// Print each new paper to stdout as a line of JSON as soon as it is
+    /// recorded. The csv file is written as usual and logs go to stderr.
+    #[arg(long)]
+    pub stream: bool,
+
+    /// Bundle the csv file, the saved state and the settings into a zip
+    /// archive and exit.
+    #[arg(long, value_name = "PATH")]
+    pub export: Option<PathBuf>,
+
+    /// Restore the files bundled by "--export" and exit.
+    #[arg(long, value_name = "PATH", conflicts_with = "export")]
+    pub import: Option<PathBuf>,
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|e| format!("'{}' is not a valid YYYY-MM-DD date: {}", date, e))
+}
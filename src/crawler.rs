@@ -1,15 +1,21 @@
+use std::cell::Cell;
+use std::collections::HashSet;
 use std::ffi::OsString;
-use std::fmt::Write;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use chrono::prelude::*;
-use headless_chrome::{Browser, Element, LaunchOptionsBuilder, Tab};
-use rayon::prelude::*;
+use headless_chrome::{Browser, LaunchOptionsBuilder, Tab};
 
-use crate::storage::{Paper, Storage};
+use crate::errors::BlockedException;
+use crate::report::SearchReport;
+use crate::source::{ScienceDirect, SearchSource};
+use crate::storage::{cap_papers, score_paper, sort_papers, write_json_line, Paper, Storage};
 use crate::Exception;
 
+const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/105.0.0.0 Safari/537.36";
+
 /// # ChromeDriver
 ///
 /// Blocking client
@@ -17,11 +23,15 @@ pub struct ChromeDriver {
     #[allow(unused)]
     browser: Browser,
     main_tab: Arc<Tab>,
-    domain_string: String,
-    base_query_string: String,
-    blank_token: String,
-    max_indices_per_page: usize,
+    source: Box<dyn SearchSource>,
     storage: Arc<Storage>,
+    user_agents: Vec<String>,
+    next_agent: Cell<usize>,
+    robots_delay: Option<Duration>,
+    stream: bool,
+    /// The keywords that failed in the last search.
+    failed_keyword: Vec<String>,
+    last_keep_alive: Instant,
 }
 
 impl ChromeDriver {
@@ -32,154 +42,909 @@ impl ChromeDriver {
     /// that returns a shared reference to the current window handle. Javascript Window object
     /// can be mutated at any point without the Rust implementation of interior mutability.
     pub fn new() -> Result<Self, Exception> {
-        let user_agent = OsString::from("--user-agent=Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/105.0.0.0 Safari/537.36");
+        let storage = Arc::new(Storage::new()?);
+        let (results_per_page, _) = storage.pages_from_settings();
+        let (search_field, sort_by) = storage.search_field_from_settings();
+
+        let mut user_agents = storage.user_agents_from_settings();
+        if user_agents.is_empty() {
+            user_agents.push(USER_AGENT.to_string());
+        }
+        let (browser, main_tab) = Self::launch(&storage, &user_agents[0])?;
+
+        let mut me = Self {
+            browser,
+            main_tab,
+            source: Box::new(ScienceDirect::new(
+                &storage.base_url_from_settings(),
+                results_per_page,
+                search_field,
+                sort_by,
+            )),
+            storage,
+            user_agents,
+            next_agent: Cell::new(0),
+            robots_delay: None,
+            stream: false,
+            failed_keyword: Vec::new(),
+            last_keep_alive: Instant::now(),
+        };
+
+        // Never crawl faster than the site asks to.
+        if me.storage.respect_robots_from_settings() {
+            match me.fetch_robots_delay() {
+                Ok(robots_delay) => me.robots_delay = robots_delay,
+                Err(e) => tracing::warn!("Failed to read robots.txt: {}", e),
+            }
+        }
+        tracing::info!("Crawl delay: {}ms", me.crawl_delay().as_millis());
+        Ok(me)
+    }
+
+    /// Launches the browser with the "[browser]" settings and waits for its
+    /// first tab.
+    fn launch(storage: &Storage, user_agent: &str) -> Result<(Browser, Arc<Tab>), Exception> {
+        let (headless, window_size) = storage.browser_from_settings();
+        let mut args = vec![OsString::from(format!("--user-agent={}", user_agent))];
+        let language = storage.language_from_settings();
+        if let Some(language) = &language {
+            args.push(OsString::from(format!("--accept-lang={}", language)));
+        }
+        let (proxy, proxy_bypass) = storage.proxy_from_settings();
+        if let Some(proxy) = &proxy {
+            args.push(OsString::from(format!("--proxy-server={}", proxy)));
+            if !proxy_bypass.is_empty() {
+                let bypass_list = proxy_bypass.join(";");
+                args.push(OsString::from(format!(
+                    "--proxy-bypass-list={}",
+                    bypass_list
+                )));
+            }
+        }
+
         let options = LaunchOptionsBuilder::default()
-            .args(vec![&user_agent])
-            .headless(true)
+            .args(args.iter().map(OsString::as_os_str).collect())
+            .headless(headless)
+            .window_size(window_size)
             .build()?;
         let browser = Browser::new(options)?;
         let main_tab = browser.wait_for_initial_tab()?;
+        if let Some(language) = &language {
+            main_tab.set_user_agent(user_agent, Some(language), None)?;
+            tracing::info!("Accept-Language: {}", language);
+        }
+        match window_size {
+            Some((width, height)) => {
+                tracing::info!("Browser: headless = {}, {}x{}", headless, width, height)
+            }
+            None => tracing::info!("Browser: headless = {}", headless),
+        }
+        if let Some(proxy) = &proxy {
+            tracing::info!("Proxy: {}", redact_proxy(proxy));
+        }
+        Ok((browser, main_tab))
+    }
 
-        Ok(Self {
-            browser,
-            main_tab,
-            domain_string: "https://www.sciencedirect.com/".into(),
-            base_query_string: "https://www.sciencedirect.com/search?qs=".into(),
-            blank_token: "%20".into(),
-            max_indices_per_page: 50,
-            storage: Arc::new(Storage::new()),
-        })
-    }
-
-    /// Adds a new keyword to search for.
-    fn query_from_keyword(&self, keyword: &str) -> Result<String, Exception> {
-        // Split keyword argument at whitespaces into a token vector.
-        let token = keyword
-            .split_ascii_whitespace()
-            .into_iter()
-            .map(String::from)
-            .collect::<Vec<String>>();
+    /// Returns false when the browser has crashed or was killed.
+    fn is_alive(&self) -> bool {
+        self.browser.get_version().is_ok()
+    }
+
+    /// Launches a new browser in place of the dead one. A failed launch is
+    /// retried up to "max_retries" times, waiting 2s, 4s, 8s, .. in between.
+    fn reconnect(&mut self) -> Result<(), Exception> {
+        tracing::warn!("The browser is not responding, relaunching it");
+        let max_retries = self.storage.max_retries_from_settings();
+        let mut retry = 0;
+        loop {
+            match Self::launch(&self.storage, &self.user_agents[0]) {
+                Ok((browser, main_tab)) => {
+                    self.browser = browser;
+                    self.main_tab = main_tab;
+                    return Ok(());
+                }
+                Err(e) if retry < max_retries => {
+                    let backoff = Duration::from_secs(2u64.saturating_pow(retry + 1));
+                    retry += 1;
+                    tracing::warn!(
+                        "Failed to relaunch the browser ({}/{}), retrying in {}s: {}",
+                        retry,
+                        max_retries,
+                        backoff.as_secs(),
+                        e
+                    );
+                    std::thread::sleep(backoff);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Prints each new paper to stdout as a line of JSON as it is recorded.
+    pub fn set_stream(&mut self, stream: bool) {
+        self.stream = stream;
+    }
+
+    /// Reads the crawl-delay for our user agent from the robots.txt of the
+    /// domain.
+    fn fetch_robots_delay(&self) -> Result<Option<Duration>, Exception> {
+        let url = format!("{}robots.txt", self.source.domain());
+        let robots = self
+            .main_tab
+            .navigate_to(&url)?
+            .wait_until_navigated()?
+            .find_element("body")?
+            .get_inner_text()?;
+        // The user agents take turns, so the strictest delay of them applies.
+        let delay = self
+            .user_agents
+            .iter()
+            .filter_map(|user_agent| parse_crawl_delay(&robots, user_agent))
+            .reduce(f64::max);
+        Ok(delay.map(Duration::from_secs_f64))
+    }
+
+    /// The delay between page loads and between keywords. It is never shorter than the
+    /// crawl-delay in robots.txt when "respect_robots" is set.
+    fn crawl_delay(&self) -> Duration {
+        let crawl_delay = self.storage.crawl_delay_from_settings();
+        match self.robots_delay {
+            Some(robots_delay) => crawl_delay.max(robots_delay),
+            None => crawl_delay,
+        }
+    }
+
+    /// Loads the result pages of the keyword, up to "max_pages" or the
+    /// "limit" of the keyword, and parses the papers on them. It stops at
+    /// the first page that is not full.
+    fn scrape(&self, keyword: &str, since: Option<NaiveDate>) -> Result<Vec<Paper>, Exception> {
+        let placeholder = self.storage.journal_placeholder_from_settings();
+        let (_, max_pages) = self.storage.pages_from_settings();
+        let limit = self.storage.limit_from_settings(keyword);
+        let (per_page, max_pages) = match limit {
+            Some(limit) => {
+                let per_page = self.source.page_size(limit);
+                (per_page, limit.div_ceil(per_page))
+            }
+            None => (self.source.results_per_page(), max_pages),
+        };
+        let mut paper_list = Vec::<Paper>::new();
+        for page in 0..max_pages {
+            if page > 0 {
+                std::thread::sleep(self.crawl_delay());
+            }
+            let today = self.local_now().date();
+            let url = self
+                .source
+                .build_query(keyword, since, today, page, per_page);
+            let page_list = self.scrape_page(&url, keyword, &placeholder, per_page)?;
+            let full = page_list.len() >= per_page;
+            paper_list.extend(page_list);
+            if !full {
+                break;
+            }
+        }
+        if let Some(limit) = limit {
+            paper_list.truncate(limit);
+        }
+
+        // Filtered after the pages are read, so a page of filtered papers
+        // still counts as full.
+        let count = paper_list.len();
+        paper_list.retain(|paper| self.storage.journal_allowed(&paper.journal));
+        if paper_list.len() < count {
+            tracing::info!(
+                "Filtered out {} papers of '{}' by journal",
+                count - paper_list.len(),
+                keyword
+            );
+        }
+        Ok(paper_list)
+    }
 
-        // Join tokens with "self.blank_token" separator.
-        let search_keyword = token.join(&self.blank_token);
+    /// Loads a single result page and parses the papers on it. A timed out
+    /// page is retried up to "max_retries" times, waiting 2s, 4s, 8s, .. in
+    /// between.
+    fn scrape_page(
+        &self,
+        url: &str,
+        keyword: &str,
+        placeholder: &str,
+        per_page: usize,
+    ) -> Result<Vec<Paper>, Exception> {
+        let max_retries = self.storage.max_retries_from_settings();
+        let mut retry = 0;
+        loop {
+            match self.load_results(url, keyword, placeholder, per_page) {
+                Ok(paper_list) => return Ok(paper_list),
+                // Loading again right away would only prolong the block.
+                Err(e) if e.is::<BlockedException>() => return Err(e),
+                Err(e) if retry < max_retries => {
+                    let backoff = Duration::from_secs(2u64.saturating_pow(retry + 1));
+                    retry += 1;
+                    tracing::warn!(
+                        "Failed to load '{}' ({}/{}), retrying in {}s: {}",
+                        keyword,
+                        retry,
+                        max_retries,
+                        backoff.as_secs(),
+                        e
+                    );
+                    std::thread::sleep(backoff);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Clicks "Accept" on the cookie consent banner, which may cover the
+    /// results on a fresh session. Nothing happens without the banner.
+    fn accept_consent(&self) {
+        let selector = match self.storage.consent_selector_from_settings() {
+            Some(selector) => selector,
+            None => self.source.consent_selector().to_string(),
+        };
+        if selector.is_empty() {
+            return;
+        }
+        if let Ok(button) = self.main_tab.find_element(&selector) {
+            match button.click() {
+                Ok(_) => tracing::debug!("Accepted the cookie consent banner"),
+                Err(e) => tracing::warn!("Failed to accept the cookie consent banner: {}", e),
+            }
+        }
+    }
 
-        // Build a query string from joining "self.base_query_string" and
-        // the search keyword.
-        let mut query = String::from(&self.base_query_string);
-        query.push_str(&search_keyword);
-        let _ = write!(&mut query, "&show={}", self.max_indices_per_page);
-        query.push_str("&sortBy=date");
-        Ok(query)
+    /// Scrolls down the page a step at a time to the bottom, so the results
+    /// that are rendered on scrolling are filled in.
+    fn scroll_results(&self) -> Result<(), Exception> {
+        let (steps, pause) = self.storage.scroll_from_settings();
+        for step in 1..=steps {
+            let expression = format!(
+                "window.scrollTo(0, document.body.scrollHeight * {} / {})",
+                step, steps
+            );
+            self.main_tab.evaluate(&expression, false)?;
+            std::thread::sleep(pause);
+        }
+        Ok(())
+    }
+
+    /// Navigates to the query and lets the source parse the result page.
+    fn load_results(
+        &self,
+        url: &str,
+        keyword: &str,
+        journal_placeholder: &str,
+        per_page: usize,
+    ) -> Result<Vec<Paper>, Exception> {
+        // Take the next user agent of the pool in turns.
+        if self.user_agents.len() > 1 {
+            let index = self.next_agent.get();
+            self.next_agent.set((index + 1) % self.user_agents.len());
+            let language = self.storage.language_from_settings();
+            self.main_tab
+                .set_user_agent(&self.user_agents[index], language.as_deref(), None)?;
+        }
+        self.main_tab.navigate_to(url)?.wait_until_navigated()?;
+        self.accept_consent();
+        self.scroll_results()?;
+        let timeout = self.storage.element_timeout_from_settings();
+        self.source.parse_results(
+            &self.main_tab,
+            keyword,
+            journal_placeholder,
+            per_page,
+            timeout,
+        )
     }
 
     /// The function starts searching for result for each keyword,
     /// parses the html element, filters the result and saves changes.
-    pub fn search(&mut self) -> Result<(), Exception> {
-        let outer_selector = "#srp-results-list";
-        let last_element = format!(
-            "#srp-results-list > ol > li:nth-child({})",
-            self.max_indices_per_page
-        );
+    /// Returns the new papers found in this run.
+    pub fn search(&mut self, new_keyword: HashSet<String>) -> Result<Vec<Paper>, Exception> {
+        // Record the attempt before searching so a failed run is not retried
+        // until the next slot.
+        let now = self.local_now();
+        self.storage.record_run(&new_keyword, now)?;
+        if !self.is_alive() {
+            self.reconnect()?;
+        }
 
         // Scrape the page with initialized query strings.
-        let new_keyword = self.storage.keyword_from_settings();
-        for keyword in &new_keyword {
-            let url = self.query_from_keyword(keyword)?;
-            self.main_tab
-                .navigate_to(&url)?
-                .wait_until_navigated()?
-                .wait_for_element_with_custom_timeout(
-                    &last_element,
-                    Duration::from_millis(10000),
-                )?;
+        let mut report = SearchReport::new(&new_keyword, now);
+        let mut new_paper = Vec::<(usize, Paper)>::new();
+        let bootstrap_days = self.storage.bootstrap_days_from_settings();
+        let crawl_started = Instant::now();
+        let mut blocked = false;
+        let max_papers = self.storage.max_papers_from_settings();
+        let mut truncated = 0;
+        for (index, keyword) in new_keyword.iter().enumerate() {
+            // Back off from a site that has blocked the crawler, and leave
+            // the rest of the keywords to the next run.
+            if blocked {
+                let message = "Skipped, the site blocked the crawler.".to_string();
+                report.failures.insert(keyword.to_string(), message);
+                continue;
+            }
+
+            // Pause between keywords as between pages.
+            if index > 0 {
+                std::thread::sleep(self.crawl_delay());
+            }
+
+            // A keyword searched for the first time only records what it
+            // finds, so it is enough to look back "bootstrap_days" days.
+            let since = match bootstrap_days {
+                Some(days) if !self.storage.contains_keyword(keyword) => {
+                    Some(now.date() - chrono::Duration::days(days as i64))
+                }
+                _ => None,
+            };
+            let per_page = match self.storage.limit_from_settings(keyword) {
+                Some(limit) => self.source.page_size(limit),
+                None => self.source.results_per_page(),
+            };
+            let query = self
+                .source
+                .build_query(keyword, since, now.date(), 0, per_page);
+            report.queries.insert(keyword.to_string(), query);
+
+            // A failed keyword is reported and the others are still searched.
+            let mut paper_list = match self.scrape(keyword, since) {
+                Ok(paper_list) => paper_list,
+                Err(e) => {
+                    tracing::warn!("Failed to search '{}': {}", keyword, e);
+                    blocked = e.is::<BlockedException>();
+                    report.failures.insert(keyword.to_string(), e.to_string());
+                    continue;
+                }
+            };
+            report.found.insert(keyword.to_string(), paper_list.len());
+
+            // Drop the old papers before they are recorded. The query only
+            // narrows a bootstrap to whole years, so it is cut to the day
+            // here too.
+            let (min_date, drop_undated) = self.storage.since_from_settings(now.date());
+            let min_date = min_date.max(since);
+            if let Some(min_date) = min_date {
+                paper_list.retain(|paper| match paper.published_date() {
+                    Some(published) => published >= min_date,
+                    None => !drop_undated,
+                });
+            }
+
+            // Look every paper up before any is recorded, so a failed
+            // lookup fails the keyword instead of sending its papers again.
+            let lookup_list = paper_list
+                .into_iter()
+                .map(|paper| {
+                    // The papers seen before they were keyed by DOI are
+                    // still kept by their href.
+                    let seen_before = self.storage.contains_key(&paper.href)?;
+                    let is_new = self.storage.is_new(&paper)?;
+                    Ok((paper, seen_before, is_new))
+                })
+                .collect::<Result<Vec<(Paper, bool, bool)>, Exception>>();
+            let lookup_list = match lookup_list {
+                Ok(lookup_list) => lookup_list,
+                Err(e) => {
+                    tracing::warn!("Failed to look up the papers of '{}': {}", keyword, e);
+                    report.failures.insert(keyword.to_string(), e.to_string());
+                    continue;
+                }
+            };
+
+            // Keep the papers that were not found in the previous run
+            // along with their rank in the result list.
+            let mut rank = 0;
+            for (paper, seen_before, is_new) in lookup_list {
+                // Leave the papers over the cap unseen for the next run.
+                if let Some(max_papers) = max_papers {
+                    if new_paper.len() >= max_papers && !seen_before && is_new {
+                        truncated += 1;
+                        continue;
+                    }
+                }
+                self.storage.insert(&paper);
+                if is_new && !seen_before {
+                    if self.stream {
+                        write_json_line(&mut std::io::stdout().lock(), &paper)?;
+                    }
+                    new_paper.push((rank, paper));
+                    rank += 1;
+                }
+            }
+            report.new.insert(keyword.to_string(), rank);
+        }
+        tracing::info!(
+            "Crawled {} keywords in {:.1}s",
+            new_keyword.len(),
+            crawl_started.elapsed().as_secs_f64()
+        );
+        if truncated > 0 {
+            tracing::warn!(
+                "Left {} papers over max_papers_per_run = {} for the next run",
+                truncated,
+                max_papers.unwrap_or_default()
+            );
+        }
+
+        // A failed keyword stays unseen, so it is bootstrapped again.
+        self.failed_keyword = report.failures.keys().cloned().collect();
+        let searched_keyword = new_keyword
+            .into_iter()
+            .filter(|keyword| !report.failures.contains_key(keyword))
+            .collect::<HashSet<String>>();
+        self.storage.update(searched_keyword.clone())?;
+
+        // Skip the papers that were already sent within the window.
+        let suppress_repeat_hours = self.storage.suppress_repeat_from_settings();
+        if let Some(hours) = suppress_repeat_hours {
+            let count = new_paper.len();
+            new_paper
+                .retain(|(_, paper)| !self.storage.notified_within(&paper.dedup_key(), now, hours));
+            if new_paper.len() < count {
+                tracing::info!(
+                    "Suppressed {} papers sent within {} hours",
+                    count - new_paper.len(),
+                    hours
+                );
+            }
+        }
+
+        let new_paper = self.report(new_paper, false, truncated)?;
+        // Only a run that was reported counts for the catch-up.
+        self.storage.record_success(&searched_keyword, now)?;
+
+        // Nothing is sent on a dry run.
+        if let Some(hours) = suppress_repeat_hours {
+            if !self.storage.dry_run_from_settings() {
+                self.storage.record_notified(&new_paper, now, hours)?;
+            }
+        }
+
+        report.finished_at = Some(self.local_now());
+        report.log_summary();
+        if self.storage.write_run_metadata_from_settings() {
+            report.save()?;
+        }
+        Ok(new_paper)
+    }
+
+    /// Searches the keywords and reports every paper found, including the
+    /// ones seen before, labeled as a full snapshot. The seen papers and
+    /// the schedule are left as is.
+    pub fn full_digest(&self, keyword: HashSet<String>) -> Result<Vec<Paper>, Exception> {
+        let mut paper_list = Vec::<(usize, Paper)>::new();
+        for (index, keyword) in keyword.iter().enumerate() {
+            if index > 0 {
+                std::thread::sleep(self.crawl_delay());
+            }
+            let papers = self.scrape(keyword, None)?;
+            paper_list.extend(papers.into_iter().enumerate());
+        }
+        self.report(paper_list, true, 0)
+    }
 
-            // Timeout set to 10 seconds.
-            let result_list = self.main_tab.wait_for_element_with_custom_timeout(
-                outer_selector,
-                Duration::from_millis(10000),
-            )?;
-            let li_list = result_list.wait_for_elements("li")?;
+    /// Caps, scores and sorts the papers found in a run, then writes them
+    /// to the csv file and sends them by email. "truncated" is the number of
+    /// papers left over "max_papers_per_run", which the email notes.
+    fn report(
+        &self,
+        mut new_paper: Vec<(usize, Paper)>,
+        snapshot: bool,
+        truncated: usize,
+    ) -> Result<Vec<Paper>, Exception> {
+        // Each keyword's list is in the order of the site, so the papers
+        // collected across the keywords are ordered by date before the cap.
+        if let Some(max_results) = self.storage.max_results_from_settings() {
+            let dropped = cap_papers(&mut new_paper, max_results);
+            if dropped > 0 {
+                tracing::info!(
+                    "Dropped {} papers over max_results_per_run = {}",
+                    dropped,
+                    max_results
+                );
+            }
+        }
+
+        // Score the papers while their rank is still known.
+        let scoring = self.storage.scoring_from_settings();
+        let mut new_paper: Vec<Paper> = new_paper
+            .into_iter()
+            .map(|(rank, mut paper)| {
+                paper.score = score_paper(&paper, rank, &scoring);
+                paper
+            })
+            .collect();
 
-            // Parallel parse() execution.
-            self.parse(li_list, keyword, &self.domain_string)?;
+        // Visit the page of each paper for its abstract.
+        if self.storage.fetch_abstracts_from_settings() {
+            for paper in new_paper.iter_mut() {
+                match self.fetch_abstract(&paper.href) {
+                    Ok(abstract_text) => {
+                        paper.abstract_text = Some(abstract_text);
+                        paper.match_location = paper.locate_keyword();
+                    }
+                    Err(e) => {
+                        tracing::warn!("Failed to fetch the abstract of {}: {}", paper.href, e);
+                    }
+                }
+                std::thread::sleep(self.crawl_delay());
+            }
         }
-        self.storage.update(new_keyword);
+
+        // Keep the abstracts short enough for the email and the csv file.
+        if let Some(max_chars) = self.storage.abstract_max_chars_from_settings() {
+            for paper in new_paper.iter_mut() {
+                if let Some(abstract_text) = &paper.abstract_text {
+                    paper.abstract_text = Some(truncate(abstract_text, max_chars));
+                }
+            }
+        }
+
+        sort_papers(&mut new_paper, self.storage.display_sort_from_settings());
+
+        // Only log the papers on a dry run.
+        if self.storage.dry_run_from_settings() {
+            for paper in &new_paper {
+                tracing::info!("[dry run] {:?}", paper);
+            }
+            tracing::info!("[dry run] {} papers", new_paper.len());
+            return Ok(new_paper);
+        }
+
+        // Write to the file in the display order.
+        for paper in &new_paper {
+            self.storage.write_to_file(paper.clone())?;
+        }
+        self.storage.write_json(&new_paper)?;
 
         // Send an email, if and only if the list is not empty.
-        let local_time = Local::now().naive_local().to_string();
-        self.storage.send_email(&local_time)?;
+        let local_time = self.local_now().to_string();
+        self.storage
+            .send_email(&local_time, &new_paper, snapshot, truncated)?;
 
         // Get a new file handle.
         self.storage.new_file_handle()?;
-        Ok(())
+        Ok(new_paper)
     }
 
-    /// Multi-threaded parser utilizing ["rayon"].
-    fn parse(&self, item_list: Vec<Element>, keyword: &str, domain: &str) -> Result<(), Exception> {
-        let storage = self.storage.clone();
+    /// Searches the keywords for papers published since "since" and returns
+    /// all of them, including the ones seen before. Nothing is recorded,
+    /// written or sent.
+    pub fn search_since(
+        &self,
+        keyword: HashSet<String>,
+        since: NaiveDate,
+    ) -> Result<Vec<Paper>, Exception> {
+        self.scrape_all(keyword.iter().map(String::as_str), Some(since))
+    }
 
-        // Parse items in the list.
-        item_list.par_iter().for_each(|item| {
-            // Get attributes to check if the html element contains a valid result.
-            let attr = item.get_attributes().unwrap().unwrap();
+    /// Launches the browser, searches the keywords once and returns every
+    /// paper found, for a program that embeds the crawler. Nothing is
+    /// scheduled, recorded or sent.
+    pub fn search_once(keywords: &[String]) -> Result<Vec<Paper>, Exception> {
+        let crawler = Self::new()?;
+        crawler.scrape_all(keywords.iter().map(String::as_str), None)
+    }
 
-            // Continue when "!attr.is_empty() and exclude the download link."
-            if !attr.is_empty() && attr.len() == 4 {
-                let elements = item.wait_for_elements("a").unwrap();
+    /// Scrapes the keywords one after another and sorts all the papers
+    /// found as the settings say.
+    fn scrape_all<'a>(
+        &self,
+        keyword: impl Iterator<Item = &'a str>,
+        since: Option<NaiveDate>,
+    ) -> Result<Vec<Paper>, Exception> {
+        let mut paper_list = Vec::<Paper>::new();
+        for (index, keyword) in keyword.enumerate() {
+            if index > 0 {
+                std::thread::sleep(self.crawl_delay());
+            }
+            paper_list.extend(self.scrape(keyword, since)?);
+        }
 
-                // Parse href and uref out of the content string.
-                let href = {
-                    let content = elements[0].get_content().unwrap();
-                    let tokens: Vec<_> = content.split('"').collect();
+        // The query only filters by year, so the papers before the day are
+        // dropped here.
+        if let Some(since) = since {
+            paper_list.retain(|paper| paper.published_since(since));
+        }
+        sort_papers(&mut paper_list, self.storage.display_sort_from_settings());
+        Ok(paper_list)
+    }
 
-                    // The complete href.
-                    let mut href = String::from(domain);
-                    href.push_str(tokens[3]);
+    /// Navigates to the paper page and reads the abstract, waiting at most
+    /// "abstract_timeout_ms" for it to render.
+    fn fetch_abstract(&self, href: &str) -> Result<String, Exception> {
+        let timeout = self.storage.abstract_timeout_from_settings();
+        let abstract_text = self
+            .main_tab
+            .navigate_to(href)?
+            .wait_until_navigated()?
+            .wait_for_element_with_custom_timeout(self.source.abstract_selector(), timeout)?
+            .get_inner_text()?;
+        Ok(abstract_text)
+    }
 
-                    href
-                };
+    /// The current time in the time zone of the schedule.
+    pub fn local_now(&self) -> NaiveDateTime {
+        self.storage.local_now()
+    }
 
-                // Build the paper struct.
-                let paper = Paper {
-                    title: elements[0].get_inner_text().unwrap(),
-                    href: href.to_string(),
-                    keyword: keyword.into(),
-                    journal: elements[1].get_inner_text().unwrap(),
-                };
+    /// Reports the error of a failed run by email, when "error_notify" is set.
+    pub fn notify_error(&self, error: &str) -> Result<(), Exception> {
+        self.storage.send_error_email(error, self.local_now())
+    }
 
-                // Build the uid tuple
-                let uid = (keyword.to_string(), href);
-                let result = storage.insert(uid, paper.clone());
+    /// Returns the keywords whose schedule is due now.
+    pub fn is_now(&self) -> Result<HashSet<String>, Exception> {
+        // helps to soft-land changes in the "Settings.toml file", when it
+        // has been modified since it was last read.
+        self.storage.update_settings()?;
 
-                // Write to the file.
-                if result {
-                    storage.write_to_file(paper).unwrap();
-                }
-            }
-        });
-        Ok(())
+        // Compare local time with the schedule of each keyword.
+        let local_time = self.local_now();
+        let last_run = self.storage.last_run_from_state();
+        let grace_minutes = self.storage.grace_from_settings();
+        let due_keyword = self
+            .storage
+            .schedule_from_settings()
+            .into_iter()
+            .filter(|(keyword, schedule)| {
+                schedule.is_due(local_time, last_run.get(keyword).copied(), grace_minutes)
+            })
+            .map(|(keyword, _)| keyword)
+            .collect();
+        Ok(due_keyword)
     }
 
-    fn local_now(&self) -> (u32, u32, Weekday) {
-        let local = Local::now();
-        (local.hour(), local.minute(), local.weekday())
+    /// The keywords that failed in the last search, sorted.
+    pub fn failed_keyword(&self) -> Vec<String> {
+        self.failed_keyword.clone()
     }
 
-    pub fn is_now(&self) -> Result<bool, Exception> {
-        // helps to soft-land changes in the "Settings.toml file".
-        self.storage.update_settings()?;
+    /// Returns the keywords whose slot of today was missed, with
+    /// "catch_up". The last successful search of each keyword is kept in
+    /// the state, so it is known across restarts.
+    pub fn missed_keyword(&self) -> HashSet<String> {
+        if !self.storage.catch_up_from_settings() {
+            return HashSet::new();
+        }
+        let local_time = self.local_now();
+        let last_run = self.storage.last_success_from_state();
+        self.storage
+            .schedule_from_settings()
+            .into_iter()
+            .filter(|(keyword, schedule)| {
+                schedule.missed_today(local_time, last_run.get(keyword).copied())
+            })
+            .map(|(keyword, _)| keyword)
+            .collect()
+    }
 
-        // Compare local time with the event time.
+    /// The earliest time any keyword is due, or None without a keyword.
+    pub fn next_run_time(&self) -> Option<NaiveDateTime> {
         let local_time = self.local_now();
-        let time_set = self.storage.time_from_settings();
-        Ok(local_time == time_set)
+        let last_run = self.storage.last_run_from_state();
+        let grace_minutes = self.storage.grace_from_settings();
+        self.storage
+            .schedule_from_settings()
+            .iter()
+            .map(|(keyword, schedule)| {
+                schedule.next_run(local_time, last_run.get(keyword).copied(), grace_minutes)
+            })
+            .min()
+    }
+
+    /// Flushes the csv file and waits for the emails being sent before the
+    /// program exits.
+    pub fn flush(&self) -> Result<(), Exception> {
+        self.storage.flush()
+    }
+
+    /// The errors of the emails that gave up sending since the last call.
+    pub fn failed_emails(&self) -> Vec<String> {
+        self.storage.failed_sending()
     }
 
+    pub fn keyword_from_settings(&self) -> HashSet<String> {
+        self.storage.keyword_from_settings()
+    }
+
+    /// Keeps the browser session from timing out between the runs by
+    /// replacing the tab with a fresh one every "keep_alive_secs" seconds,
+    /// which opens "keep_alive_url" when it is set. It is only called
+    /// between the runs with the crawler locked, so it never replaces the
+    /// tab of a search in progress.
     pub fn avoid_timeout(&mut self) -> Result<(), Exception> {
+        // A dead browser can not open a tab, so it is replaced as a whole.
+        if !self.is_alive() {
+            return self.reconnect();
+        }
+
+        let (interval, keep_alive_url) = self.storage.keep_alive_from_settings();
+        if self.last_keep_alive.elapsed() < interval {
+            return Ok(());
+        }
+        self.last_keep_alive = Instant::now();
+
         let new_tab = self.browser.new_tab()?;
+        if let Some(language) = self.storage.language_from_settings() {
+            new_tab.set_user_agent(&self.user_agents[0], Some(&language), None)?;
+        }
+        if let Some(url) = &keep_alive_url {
+            new_tab.navigate_to(url)?.wait_until_navigated()?;
+        }
         let current_tab = std::mem::replace(&mut self.main_tab, new_tab);
         current_tab.close(true)?;
-        std::thread::sleep(Duration::from_millis(1600));
+        match keep_alive_url {
+            Some(url) => tracing::debug!("Keep-alive: replaced the tab and opened {}", url),
+            None => tracing::debug!("Keep-alive: replaced the tab"),
+        }
         Ok(())
     }
+
+    /// The port and the token of the control server.
+    pub fn control_from_settings(&self) -> (Option<SocketAddr>, Option<String>) {
+        self.storage.control_from_settings()
+    }
+}
+
+/// Cuts the text to "max_chars" characters, marking the cut with "…".
+fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}…", text[..end].trim_end()),
+        None => text.to_string(),
+    }
+}
+
+/// Hides the user name and the password in the proxy url for the log.
+fn redact_proxy(proxy: &str) -> String {
+    let (scheme, rest) = match proxy.split_once("://") {
+        Some((scheme, rest)) => (format!("{}://", scheme), rest),
+        None => (String::new(), proxy),
+    };
+    match rest.rsplit_once('@') {
+        Some((_, host)) => format!("{}***@{}", scheme, host),
+        None => proxy.to_string(),
+    }
+}
+
+/// Finds the crawl-delay in seconds that applies to "user_agent". A group
+/// naming one of the product tokens of the user agent wins over the "*"
+/// group.
+fn parse_crawl_delay(robots: &str, user_agent: &str) -> Option<f64> {
+    let token_list = product_tokens(user_agent);
+    let mut agent_list = Vec::<String>::new();
+    let mut in_rules = false;
+    let mut specific = None;
+    let mut wildcard = None;
+
+    for line in robots.lines() {
+        // Strip comments.
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field.trim().to_lowercase(), value.trim()),
+            None => continue,
+        };
+
+        match field.as_str() {
+            "user-agent" => {
+                // A user-agent line after rules starts a new group.
+                if in_rules {
+                    agent_list.clear();
+                    in_rules = false;
+                }
+                // An empty value names no agent.
+                let agent = value.split('/').next().unwrap_or_default().trim();
+                if !agent.is_empty() {
+                    agent_list.push(agent.to_lowercase());
+                }
+            }
+            "crawl-delay" => {
+                in_rules = true;
+                let delay = match value.parse::<f64>() {
+                    Ok(delay) if delay.is_finite() && delay >= 0.0 => delay,
+                    _ => continue,
+                };
+                for agent in &agent_list {
+                    if agent == "*" {
+                        wildcard.get_or_insert(delay);
+                    } else if token_list.contains(agent) {
+                        specific.get_or_insert(delay);
+                    }
+                }
+            }
+            _ => in_rules = true,
+        }
+    }
+    specific.or(wildcard)
+}
+
+/// The product tokens of a user agent in lowercase, the names before "/",
+/// such as "mozilla" and "chrome", including the ones in the comments.
+fn product_tokens(user_agent: &str) -> HashSet<String> {
+    user_agent
+        .split(|c: char| c.is_whitespace() || c == ';' || c == '(' || c == ')')
+        .filter_map(|part| part.split('/').next())
+        .filter(|token| !token.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHROME: &str = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 \
+                          (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36";
+
+    #[test]
+    fn crawl_delay_of_the_matching_group() {
+        let cases = [
+            // A group naming the agent wins over "*", in either order.
+            (
+                "User-agent: *\nCrawl-delay: 5\n\nUser-agent: Chrome\nCrawl-delay: 2\n",
+                Some(2.0),
+            ),
+            (
+                "User-agent: chrome\nCrawl-delay: 2\n\nUser-agent: *\nCrawl-delay: 5\n",
+                Some(2.0),
+            ),
+            // Another agent's group does not apply.
+            (
+                "User-agent: Googlebot\nCrawl-delay: 2\n\nUser-agent: *\nCrawl-delay: 5\n",
+                Some(5.0),
+            ),
+            // The user-agent lines of a group share its rules.
+            (
+                "User-agent: Googlebot\nUser-agent: Safari\nCrawl-delay: 3\n",
+                Some(3.0),
+            ),
+            // A version after the product token is ignored.
+            ("User-agent: Chrome/119\nCrawl-delay: 3\n", Some(3.0)),
+            // Comments are stripped.
+            (
+                "# Crawl-delay: 9\nUser-agent: * # everyone\nCrawl-delay: 4 # seconds\n",
+                Some(4.0),
+            ),
+            // A user-agent line after the rules starts a new group.
+            (
+                "User-agent: chrome\nDisallow: /x\nUser-agent: Googlebot\nCrawl-delay: 3\n",
+                None,
+            ),
+            ("User-agent: *\nDisallow: /search\n", None),
+        ];
+        for (robots, delay) in cases {
+            assert_eq!(parse_crawl_delay(robots, CHROME), delay, "{}", robots);
+        }
+    }
+
+    #[test]
+    fn crawl_delay_skips_invalid_values() {
+        let cases = [
+            ("User-agent: *\nCrawl-delay: -1\n", None),
+            ("User-agent: *\nCrawl-delay: soon\n", None),
+            ("User-agent: *\nCrawl-delay: NaN\n", None),
+            ("User-agent: *\nCrawl-delay: inf\n", None),
+            ("User-agent: *\nCrawl-delay: 0.5\n", Some(0.5)),
+        ];
+        for (robots, delay) in cases {
+            assert_eq!(parse_crawl_delay(robots, CHROME), delay, "{}", robots);
+        }
+    }
+
+    #[test]
+    fn crawl_delay_matches_whole_product_tokens() {
+        // An empty agent names no one, and a part of a token is no match.
+        let cases = [
+            "User-agent:\nCrawl-delay: 1\n\nUser-agent: *\nCrawl-delay: 5\n",
+            "User-agent: a\nCrawl-delay: 1\n\nUser-agent: *\nCrawl-delay: 5\n",
+            "User-agent: moz\nCrawl-delay: 1\n\nUser-agent: *\nCrawl-delay: 5\n",
+        ];
+        for robots in cases {
+            assert_eq!(parse_crawl_delay(robots, CHROME), Some(5.0), "{}", robots);
+        }
+    }
 }
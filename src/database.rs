@@ -0,0 +1,203 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Mutex;
+
+use chrono::NaiveDateTime;
+use rusqlite::{params, Connection};
+
+use crate::storage::Paper;
+use crate::Exception;
+
+/// The papers seen so far, kept in a SQLite database with
+/// "storage_backend = sqlite" instead of "state.json", so a large history
+/// is not loaded into memory.
+pub struct Database {
+    connection: Mutex<Connection>,
+}
+
+impl Database {
+    /// Opens the database at the path, creating the table on first use.
+    pub fn open(path: &Path) -> Result<Self, Exception> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    fn with_connection(connection: Connection) -> Result<Self, Exception> {
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS papers (
+                key           TEXT PRIMARY KEY,
+                keyword       TEXT NOT NULL,
+                title         TEXT NOT NULL,
+                authors       TEXT NOT NULL,
+                journal       TEXT NOT NULL,
+                published     TEXT,
+                href          TEXT NOT NULL,
+                doi           TEXT,
+                abstract_text TEXT,
+                first_seen    TEXT NOT NULL
+            )",
+            [],
+        )?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Returns true when a paper by the [Paper::dedup_key] is stored.
+    pub fn contains(&self, key: &str) -> Result<bool, Exception> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare_cached("SELECT 1 FROM papers WHERE key = ?1")?;
+        Ok(statement.exists(params![key])?)
+    }
+
+    /// Stores the papers first seen at "now". A paper stored before keeps
+    /// its first row.
+    pub fn insert(&self, papers: &[Paper], now: NaiveDateTime) -> Result<(), Exception> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        {
+            let mut statement = transaction.prepare_cached(
+                "INSERT OR IGNORE INTO papers (key, keyword, title, authors, journal,
+                    published, href, doi, abstract_text, first_seen)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            )?;
+            for paper in papers {
+                statement.execute(params![
+                    paper.dedup_key(),
+                    paper.keyword,
+                    paper.title,
+                    paper.authors.join("; "),
+                    paper.journal,
+                    paper.published,
+                    paper.href,
+                    paper.doi,
+                    paper.abstract_text,
+                    now.format("%Y-%m-%d %H:%M:%S").to_string(),
+                ])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Stores the keys of the papers seen before the database was used,
+    /// with the keyword of each. Only the key and the keyword are known.
+    pub fn insert_seen(
+        &self,
+        seen: &HashMap<String, String>,
+        now: NaiveDateTime,
+    ) -> Result<(), Exception> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        {
+            let mut statement = transaction.prepare_cached(
+                "INSERT OR IGNORE INTO papers (key, keyword, title, authors, journal,
+                    href, first_seen)
+                VALUES (?1, ?2, '', '', '', ?1, ?3)",
+            )?;
+            for (key, keyword) in seen {
+                statement.execute(params![
+                    key,
+                    keyword,
+                    now.format("%Y-%m-%d %H:%M:%S").to_string(),
+                ])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Drops the papers of the keywords removed from the settings.
+    pub fn retain_keywords(&self, keyword: &HashSet<String>) -> Result<(), Exception> {
+        let connection = self.connection.lock().unwrap();
+        let stored = connection
+            .prepare("SELECT DISTINCT keyword FROM papers")?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        for removed in stored.iter().filter(|k| !keyword.contains(*k)) {
+            connection.execute("DELETE FROM papers WHERE keyword = ?1", params![removed])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MatchLocation;
+
+    fn paper(keyword: &str, href: &str) -> Paper {
+        Paper {
+            keyword: keyword.to_string(),
+            title: "A title".to_string(),
+            authors: vec!["A. Author".to_string()],
+            journal: "A journal".to_string(),
+            published: None,
+            href: href.to_string(),
+            doi: None,
+            abstract_text: None,
+            score: 0.0,
+            match_location: MatchLocation::Neither,
+        }
+    }
+
+    fn now() -> NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(8, 30, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn contains_inserted_papers() {
+        let database = Database::with_connection(Connection::open_in_memory().unwrap()).unwrap();
+        let first = paper("ai", "https://example.com/pii/1");
+        assert!(!database.contains(&first.dedup_key()).unwrap());
+
+        database
+            .insert(&[first.clone(), first.clone()], now())
+            .unwrap();
+        assert!(database.contains(&first.dedup_key()).unwrap());
+        assert!(!database.contains("https://example.com/pii/2").unwrap());
+    }
+
+    #[test]
+    fn drops_removed_keywords() {
+        let database = Database::with_connection(Connection::open_in_memory().unwrap()).unwrap();
+        let kept = paper("ai", "https://example.com/pii/1");
+        let removed = paper("supply chain", "https://example.com/pii/2");
+        database
+            .insert(&[kept.clone(), removed.clone()], now())
+            .unwrap();
+
+        let keyword = HashSet::from(["ai".to_string()]);
+        database.retain_keywords(&keyword).unwrap();
+        assert!(database.contains(&kept.dedup_key()).unwrap());
+        assert!(!database.contains(&removed.dedup_key()).unwrap());
+    }
+
+    #[test]
+    fn seen_papers_are_moved_in_once() {
+        let database = Database::with_connection(Connection::open_in_memory().unwrap()).unwrap();
+        let first = paper("ai", "https://example.com/pii/1");
+        database.insert(&[first.clone()], now()).unwrap();
+
+        let seen = HashMap::from([
+            (first.dedup_key().to_string(), "ai".to_string()),
+            ("10.1016/j.x.2024.2".to_string(), "graphene".to_string()),
+        ]);
+        database.insert_seen(&seen, now()).unwrap();
+        assert!(database.contains(&first.dedup_key()).unwrap());
+        assert!(database.contains("10.1016/j.x.2024.2").unwrap());
+
+        // The paper stored before keeps its row.
+        let connection = database.connection.lock().unwrap();
+        let title: String = connection
+            .query_row(
+                "SELECT title FROM papers WHERE key = ?1",
+                params![first.dedup_key()],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(title, "A title");
+    }
+}
@@ -0,0 +1,186 @@
+use std::error::Error;
+use std::fmt::{Debug, Display};
+
+pub struct TimeFormatException(pub (String, String));
+
+impl Debug for TimeFormatException {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let buffer = format!(
+            "\n\t{}\n\
+            \ttime = {} is not a valid time format.\n\
+            \ttime = 'HH:MM' is the valid format.",
+            &self.0 .0, &self.0 .1
+        );
+        write!(f, "{}", buffer)
+    }
+}
+
+impl Display for TimeFormatException {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let buffer = format!(
+            "\n\t{}\n\
+            \ttime = {} is not a valid time format.\n\
+            \ttime = 'HH:MM' is the valid format.",
+            &self.0 .0, &self.0 .1
+        );
+        write!(f, "{}", buffer)
+    }
+}
+
+impl Error for TimeFormatException {}
+
+pub struct WeekdayException(pub String);
+
+impl Debug for WeekdayException {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "\n\tweekday = '{}' is not a valid weekday format.\nChoose from\n\
+            \t'Mon'\n\
+            \t'Tue'\n\
+            \t'Wed'\n\
+            \t'Thu'\n\
+            \t'Fri'\n\
+            \t'Sat'\n\
+            \t'Sun'\n\
+            \t'daily' (every day)\n\
+            \t'weekday' (Mon to Fri)\n\
+            \t'weekend' (Sat and Sun)\n",
+            &self.0
+        )
+    }
+}
+
+impl Display for WeekdayException {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "\n\tweekday = '{}' is not a valid weekday format.\nChoose from\n\
+            \t'Mon'\n\
+            \t'Tue'\n\
+            \t'Wed'\n\
+            \t'Thu'\n\
+            \t'Fri'\n\
+            \t'Sat'\n\
+            \t'Sun'\n\
+            \t'daily' (every day)\n\
+            \t'weekday' (Mon to Fri)\n\
+            \t'weekend' (Sat and Sun)\n",
+            &self.0
+        )
+    }
+}
+
+impl Error for WeekdayException {}
+
+pub struct ProfileException(pub String);
+
+impl Debug for ProfileException {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\n\t{}", &self.0)
+    }
+}
+
+impl Display for ProfileException {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\n\t{}", &self.0)
+    }
+}
+
+impl Error for ProfileException {}
+
+pub struct SettingsException(pub String);
+
+impl Debug for SettingsException {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\n\t{}", &self.0)
+    }
+}
+
+impl Display for SettingsException {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\n\t{}", &self.0)
+    }
+}
+
+impl Error for SettingsException {}
+
+pub struct ParseException(pub String);
+
+impl Debug for ParseException {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\n\t{}", &self.0)
+    }
+}
+
+impl Display for ParseException {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\n\t{}", &self.0)
+    }
+}
+
+impl Error for ParseException {}
+
+pub struct TemplateException(pub String);
+
+impl Debug for TemplateException {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\n\t{}", &self.0)
+    }
+}
+
+impl Display for TemplateException {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\n\t{}", &self.0)
+    }
+}
+
+impl Error for TemplateException {}
+
+pub struct ArchiveException(pub String);
+
+impl Debug for ArchiveException {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\n\t{}", &self.0)
+    }
+}
+
+impl Display for ArchiveException {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\n\t{}", &self.0)
+    }
+}
+
+impl Error for ArchiveException {}
+
+pub struct BlockedException(pub String);
+
+impl Debug for BlockedException {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\n\t{}", &self.0)
+    }
+}
+
+impl Display for BlockedException {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\n\t{}", &self.0)
+    }
+}
+
+impl Error for BlockedException {}
+
+pub struct EmailException(pub String);
+
+impl Debug for EmailException {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\n\t{}", &self.0)
+    }
+}
+
+impl Display for EmailException {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\n\t{}", &self.0)
+    }
+}
+
+impl Error for EmailException {}
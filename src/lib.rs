@@ -1,66 +1,481 @@
+mod archive;
+mod cli;
 mod crawler;
+mod database;
+mod errors;
+mod mail;
+mod report;
+mod schedule;
+mod server;
+mod source;
+mod state;
+mod status;
 mod storage;
 
-use std::cell::RefCell;
 use std::env::current_dir;
 use std::path::PathBuf;
-use std::rc::Rc;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
+use clap::Parser;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+use cli::Cli;
 use crawler::ChromeDriver;
+use errors::{
+    EmailException, ProfileException, SettingsException, TemplateException, TimeFormatException,
+    WeekdayException,
+};
+use status::Status;
+
+/// The crawler, for a program that runs a search on its own with
+/// [Crawler::search_once] instead of [run_app].
+pub use crawler::ChromeDriver as Crawler;
+pub use storage::{MatchLocation, Paper};
+
+/// The shortest and the longest sleep between two checks of the schedule.
+const MIN_SLEEP: Duration = Duration::from_secs(1);
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// The exit codes of "--once" besides 0, a run that found new papers. Any
+/// other error exits with 1.
+const EXIT_NO_PAPERS: u8 = 2;
+const EXIT_CRAWL_ERROR: u8 = 3;
+const EXIT_EMAIL_ERROR: u8 = 4;
+const EXIT_CONFIG_ERROR: u8 = 5;
+
+/// "--config" in place of "./Settings.toml".
+static SETTINGS_PATH: OnceLock<PathBuf> = OnceLock::new();
+/// "--dry-run", which turns on "dry_run" whatever the settings say.
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/// Reads the settings from "path" instead of "./Settings.toml", as
+/// "--config" does. It only takes effect before the settings are first read.
+pub fn set_settings_path(path: PathBuf) {
+    let _ = SETTINGS_PATH.set(path);
+}
 
 /// Type aliasing for Box<dyn std::error::Error> that is used globally.
 pub type Exception = Box<dyn std::error::Error>;
 
-/// The entry point of the app.
-pub fn run_app() -> Result<(), Exception> {
-    tracing_subscriber::fmt()
-        .pretty()
-        .init();
+/// The entry point of the app. "--once" exits with the code of its result
+/// instead of failing.
+pub fn run_app() -> Result<ExitCode, Exception> {
+    let cli = Cli::parse();
+
+    // Apply the overrides before the settings are read for the first time.
+    if let Some(path) = cli.config {
+        set_settings_path(path);
+    }
+    DRY_RUN.store(cli.dry_run, Ordering::SeqCst);
+
+    // Hold the guard until the end so the log file is flushed.
+    let _log_guard = match init_logging() {
+        Ok(log_guard) => log_guard,
+        Err(e) if cli.once => {
+            eprintln!("Failed to read the settings: {}", e);
+            return Ok(ExitCode::from(EXIT_CONFIG_ERROR));
+        }
+        Err(e) => return Err(e),
+    };
 
-    // Initialize the crawler and the flag as a mutable reference.
-    let web_driver = ChromeDriver::new()?;
+    // Run every check of the settings and exit.
+    if cli.validate_config {
+        let settings_path = load_settings_path()?;
+        return match storage::Settings::new() {
+            Ok(settings) => {
+                println!(
+                    "OK: {} ({} keywords)",
+                    settings_path.display(),
+                    settings.keyword.len()
+                );
+                Ok(ExitCode::SUCCESS)
+            }
+            Err(e) => {
+                let message = format!("{} is not valid: {}", settings_path.display(), e);
+                Err(Box::new(SettingsException(message)))
+            }
+        };
+    }
+
+    // Check the SMTP settings without crawling.
+    if cli.test_email {
+        let settings = storage::Settings::new()?;
+        let csv_path = load_csv_path()?;
+        let csv_path = match std::fs::metadata(&csv_path) {
+            Ok(metadata) if metadata.len() > 0 => Some(csv_path),
+            _ => None,
+        };
+        let response = settings.send_test_email(csv_path.as_deref())?;
+        println!("OK: sent to {} ({})", settings.email, response);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // Move the files between machines before the crawler opens them.
+    if let Some(path) = cli.export {
+        archive::export(&path)?;
+        tracing::info!("Exported to {}", path.display());
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(path) = cli.import {
+        archive::import(&path)?;
+        tracing::info!("Imported from {}", path.display());
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // Initialize the crawler as a mutable reference.
+    let mut web_driver = match ChromeDriver::new() {
+        Ok(web_driver) => web_driver,
+        Err(e) if cli.once => {
+            tracing::error!("Failed to start: {}", e);
+            return Ok(ExitCode::from(exit_code(&e)));
+        }
+        Err(e) => return Err(e),
+    };
+    web_driver.set_stream(cli.stream);
     tracing::info!("Initialize the Chrome web driver");
-    
-    let crawler = Rc::new(RefCell::new(web_driver));
-    let flag = Rc::new(RefCell::new(false));
+
+    let keyword = if cli.keyword.is_empty() {
+        web_driver.keyword_from_settings()
+    } else {
+        cli.keyword.into_iter().collect()
+    };
+
+    // Search once and exit with the code of the result. A keyword that
+    // failed is a crawl error even when the others found papers.
+    if cli.once {
+        let result = web_driver.search(keyword).and_then(|papers| {
+            // Wait for the email before exiting.
+            web_driver.flush()?;
+            Ok(papers)
+        });
+        let failed_keyword = web_driver.failed_keyword();
+        return Ok(match result {
+            Err(e) => {
+                tracing::error!("Search failed: {}", e);
+                ExitCode::from(exit_code(&e))
+            }
+            Ok(_) if !failed_keyword.is_empty() => {
+                tracing::error!("Failed to search {:?}", failed_keyword);
+                ExitCode::from(EXIT_CRAWL_ERROR)
+            }
+            Ok(papers) if papers.is_empty() => {
+                tracing::info!("Found no new papers");
+                ExitCode::from(EXIT_NO_PAPERS)
+            }
+            Ok(papers) => {
+                tracing::info!("Found {} new papers", papers.len());
+                ExitCode::SUCCESS
+            }
+        });
+    }
+
+    // Send everything that matches the keywords now and exit.
+    if cli.full_digest {
+        let papers = web_driver.full_digest(keyword)?;
+        web_driver.flush()?;
+        tracing::info!("Sent a full snapshot of {} papers", papers.len());
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // Catch up on the papers since the date and exit.
+    if let Some(since) = cli.since {
+        let papers = web_driver.search_since(keyword, since)?;
+
+        let mut writer = storage::csv_writer(std::io::stdout());
+        storage::write_csv_header(&mut writer)?;
+        for paper in &papers {
+            storage::write_csv_paper(&mut writer, paper)?;
+        }
+        writer.flush()?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let crawler = Arc::new(Mutex::new(web_driver));
+
+    // Serve "POST /run" on the control port, sharing the crawler.
+    let (control_address, control_token) = crawler
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .control_from_settings();
+    if let Some(address) = control_address {
+        server::spawn(crawler.clone(), address, control_token)?;
+    }
+    // Stop at the top of the loop on Ctrl-C, so a search in progress
+    // finishes writing the csv file.
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let shutdown_flag = shutdown.clone();
+    ctrlc::set_handler(move || shutdown_flag.store(true, Ordering::SeqCst))?;
     tracing::info!("running..");
 
+    // Show when the crawler fires next, so a wrong schedule is seen at once.
+    let (next_run, now) = {
+        let crawler = crawler.lock().unwrap_or_else(|e| e.into_inner());
+        (crawler.next_run_time(), crawler.local_now())
+    };
+    match next_run {
+        Some(next_run) => {
+            let wait = next_run - now;
+            tracing::info!(
+                "next run: {} (in {}d {}h {}m)",
+                next_run.format("%a %H:%M"),
+                wait.num_days(),
+                wait.num_hours() % 24,
+                wait.num_minutes() % 60
+            );
+        }
+        None => tracing::warn!("No keyword is scheduled. Check the keyword in Settings.toml"),
+    }
+    let mut status = Status::load().unwrap_or_else(|e| {
+        tracing::warn!("Failed to load the saved status: {}", e);
+        Status::default()
+    });
+
+    // Search at once the keywords missed while the crawler was down.
+    {
+        let mut crawler_mut = crawler.lock().unwrap_or_else(|e| e.into_inner());
+        let missed_keyword = crawler_mut.missed_keyword();
+        if !missed_keyword.is_empty() {
+            tracing::info!("Catching up on the missed keywords: {:?}", missed_keyword);
+            let result = crawler_mut.search(missed_keyword);
+            status.record_run(crawler_mut.local_now(), &result);
+            match result {
+                Ok(papers) => tracing::info!("Found {} new papers", papers.len()),
+                Err(e) => {
+                    tracing::error!("Search failed: {}", e);
+                    if let Err(e) = crawler_mut.notify_error(&e.to_string()) {
+                        tracing::warn!("Failed to send the error report: {}", e);
+                    }
+                }
+            }
+        }
+    }
+
     loop {
-        let mut crawler_mut = crawler.borrow_mut();
+        let mut crawler_mut = crawler.lock().unwrap_or_else(|e| e.into_inner());
         crawler_mut.avoid_timeout()?;
         match crawler_mut.is_now() {
-            Ok(bool_value) => {
-                if bool_value {
-                    // Set the event off only when
-                    // "bool_value" == true && "flag" == false.
-                    let mut flag_mut = flag.borrow_mut();
-                    if !(*flag_mut) {
-                        match crawler_mut.search() {
-                            Ok(()) => {}
-                            Err(e) => {
-                                dbg!(e);
+            Ok(mut due_keyword) => {
+                // A "trigger" file searches every keyword now, whatever the
+                // schedule says.
+                if take_trigger() {
+                    tracing::info!("Triggered a search of every keyword");
+                    due_keyword = crawler_mut.keyword_from_settings();
+                }
+
+                // Search the keywords whose schedule is due.
+                if !due_keyword.is_empty() {
+                    let result = crawler_mut.search(due_keyword);
+                    status.record_run(crawler_mut.local_now(), &result);
+                    match result {
+                        Ok(papers) => tracing::info!("Found {} new papers", papers.len()),
+                        Err(e) => {
+                            tracing::error!("Search failed: {}", e);
+                            if let Err(e) = crawler_mut.notify_error(&e.to_string()) {
+                                tracing::warn!("Failed to send the error report: {}", e);
                             }
                         }
-                        *flag_mut = true;
-                        continue;
-                    } else {
-                        continue;
                     }
-                } else {
-                    // Otherwise, set the flag back to false.
-                    let mut flag_mut = flag.borrow_mut();
-                    *flag_mut = false;
                 }
             }
             Err(e) => {
-                dbg!(e);
+                tracing::error!("Failed to check the schedule: {}", e);
+            }
+        }
+
+        // An email that gave up sending is reported like a failed search.
+        for error in crawler_mut.failed_emails() {
+            if let Err(e) = crawler_mut.notify_error(&error) {
+                tracing::warn!("Failed to send the error report: {}", e);
+            }
+        }
+
+        // Show that the crawler is alive, whether it searched or not.
+        status.updated_at = Some(crawler_mut.local_now());
+        if let Err(e) = status.save() {
+            tracing::warn!("Failed to save the status: {}", e);
+        }
+
+        // Sleep until the next run, but wake up at least once a minute to
+        // pick up the changes in Settings.toml.
+        let wait = match crawler_mut.next_run_time() {
+            Some(next_run) => (next_run - crawler_mut.local_now())
+                .to_std()
+                .unwrap_or_default(),
+            None => MAX_SLEEP,
+        };
+        drop(crawler_mut);
+
+        // Sleep without holding the lock so the control server can run, in
+        // short steps so Ctrl-C is not kept waiting.
+        let wake = Instant::now() + wait.clamp(MIN_SLEEP, MAX_SLEEP);
+        while let Some(left) = wake.checked_duration_since(Instant::now()) {
+            if shutdown.load(Ordering::SeqCst) {
+                crawler.lock().unwrap_or_else(|e| e.into_inner()).flush()?;
+                tracing::info!("Shut down");
+                return Ok(ExitCode::SUCCESS);
+            }
+            if load_trigger_path().map_or(false, |path| path.exists()) {
+                break;
             }
+            std::thread::sleep(left.min(Duration::from_secs(1)));
         }
     }
 }
 
+/// The exit code of "--once" for the error: a bad setting, an email that
+/// could not be sent, or else a failed crawl.
+fn exit_code(e: &Exception) -> u8 {
+    if e.is::<SettingsException>()
+        || e.is::<ProfileException>()
+        || e.is::<TemplateException>()
+        || e.is::<TimeFormatException>()
+        || e.is::<WeekdayException>()
+        || e.is::<config::ConfigError>()
+    {
+        EXIT_CONFIG_ERROR
+    } else if e.is::<EmailException>() {
+        EXIT_EMAIL_ERROR
+    } else {
+        EXIT_CRAWL_ERROR
+    }
+}
+
+/// Logs to stderr, keeping stdout for the papers, and also to a file rotated
+/// every day with "log_file". "RUST_LOG" takes over "log_level" when set.
+/// ```
+/// log_level = "info"
+/// log_file = "/var/log/linkdrive/linkdrive.log"
+/// ```
+fn init_logging() -> Result<Option<WorkerGuard>, Exception> {
+    // A missing or broken settings file is reported once the logger is up.
+    let config = match load_settings_path()?.exists() {
+        true => storage::Settings::load_config().ok(),
+        false => None,
+    };
+    let setting = |key: &str| {
+        config
+            .as_ref()
+            .and_then(|config| config.get_string(&format!("default.{}", key)).ok())
+    };
+
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => {
+            let log_level = setting("log_level").unwrap_or_else(|| "info".to_string());
+            match EnvFilter::try_new(&log_level) {
+                Ok(filter) => filter,
+                Err(e) => {
+                    let message = format!("log_level = '{}' is invalid: {}", log_level, e);
+                    return Err(Box::new(SettingsException(message)));
+                }
+            }
+        }
+    };
+
+    let (file_layer, guard) = match setting("log_file") {
+        Some(log_file) => {
+            let log_file = PathBuf::from(log_file);
+            let directory = match log_file.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => current_dir()?,
+            };
+            std::fs::create_dir_all(&directory)?;
+            let file_name = match log_file.file_name() {
+                Some(file_name) => file_name.to_os_string(),
+                None => "linkdrive.log".into(),
+            };
+            let appender = tracing_appender::rolling::daily(directory, file_name);
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            let layer = tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(writer);
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(
+            tracing_subscriber::fmt::layer()
+                .pretty()
+                .with_writer(std::io::stderr),
+        )
+        .with(file_layer)
+        .init();
+    Ok(guard)
+}
+
+/// "./Papers.csv" unless "output_path" in Settings.toml says otherwise.
+/// ```
+/// output_path = "/var/lib/linkdrive/Papers.csv"
+/// ```
 fn load_csv_path() -> Result<PathBuf, Exception> {
+    let config = storage::Settings::load_config()?;
+    if let Ok(output_path) = config.get_string("default.output_path") {
+        let csv_path = PathBuf::from(output_path);
+        if let Some(parent) = csv_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        return Ok(csv_path);
+    }
+
     let mut csv_path = current_dir()?;
     csv_path.push("Papers.csv");
     Ok(csv_path)
 }
+
+/// "Papers.meta.json" next to "Papers.csv", named after the csv file.
+fn load_meta_path() -> Result<PathBuf, Exception> {
+    let meta_path = load_csv_path()?.with_extension("meta.json");
+    Ok(meta_path)
+}
+
+/// "status.json" in the folder of the csv file.
+fn load_status_path() -> Result<PathBuf, Exception> {
+    let status_path = load_csv_path()?.with_file_name("status.json");
+    Ok(status_path)
+}
+
+/// "papers.db" in the folder of the csv file.
+fn load_database_path() -> Result<PathBuf, Exception> {
+    let database_path = load_csv_path()?.with_file_name("papers.db");
+    Ok(database_path)
+}
+
+fn load_settings_path() -> Result<PathBuf, Exception> {
+    if let Some(settings_path) = SETTINGS_PATH.get() {
+        return Ok(settings_path.clone());
+    }
+
+    let mut settings_path = current_dir()?;
+    settings_path.push("Settings.toml");
+    Ok(settings_path)
+}
+
+/// "./trigger", which is created by hand to force a search.
+fn load_trigger_path() -> Result<PathBuf, Exception> {
+    let mut trigger_path = current_dir()?;
+    trigger_path.push("trigger");
+    Ok(trigger_path)
+}
+
+/// Removes the trigger file. Returns true when it was there.
+fn take_trigger() -> bool {
+    match load_trigger_path() {
+        Ok(trigger_path) => std::fs::remove_file(trigger_path).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// "state.json" in the folder of the csv file.
+fn load_state_path() -> Result<PathBuf, Exception> {
+    let state_path = load_csv_path()?.with_file_name("state.json");
+    Ok(state_path)
+}
@@ -0,0 +1,289 @@
+use std::fmt::Write;
+use std::path::Path;
+
+use config::Config;
+
+use crate::errors::TemplateException;
+use crate::storage::Paper;
+use crate::Exception;
+
+/// Builds the email body from the papers of the run. Each paper is rendered
+/// with "entry", and only the first "max_entries" papers are listed. The
+/// rest are summed up in a note, since the attachment still has all of them.
+pub fn build_body<F>(papers: &[Paper], max_entries: Option<usize>, entry: F) -> String
+where
+    F: Fn(&Paper) -> String,
+{
+    let shown = max_entries.unwrap_or(papers.len()).min(papers.len());
+    let mut body: String = papers[..shown].iter().map(entry).collect();
+    if shown < papers.len() {
+        let _ = writeln!(body, "…and {} more (see attachment)", papers.len() - shown);
+    }
+    body
+}
+
+/// Builds the HTML version of the email body, a table of the papers with
+/// their titles linked. Like [build_body], only the first "max_entries"
+/// papers are listed.
+pub fn build_html(heading: &str, papers: &[Paper], max_entries: Option<usize>) -> String {
+    let shown = max_entries.unwrap_or(papers.len()).min(papers.len());
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<html><body>\n<h3>{}</h3>\n<table border=\"1\" cellpadding=\"4\" cellspacing=\"0\">\n\
+        <tr><th>Title</th><th>Journal</th><th>Keyword</th></tr>\n",
+        escape_html(heading)
+    );
+    for paper in &papers[..shown] {
+        let _ = writeln!(
+            html,
+            "<tr><td><a href=\"{}\">{}</a>{}</td><td>{}</td><td>{}<br><small>in {}</small></td></tr>",
+            escape_html(&paper.href),
+            escape_html(&paper.title),
+            match &paper.abstract_text {
+                Some(abstract_text) => format!("<br><small>{}</small>", escape_html(abstract_text)),
+                None => String::new(),
+            },
+            escape_html(&paper.journal),
+            escape_html(&paper.keyword),
+            paper.match_location.as_str(),
+        );
+    }
+    html.push_str("</table>\n");
+    if shown < papers.len() {
+        let _ = writeln!(
+            html,
+            "<p>…and {} more (see attachment)</p>",
+            papers.len() - shown
+        );
+    }
+    html.push_str("</body></html>\n");
+    html
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The wording of the email. It can be loaded from a template file to
+/// translate the email without recompiling.
+///
+/// ```
+/// subject = "{count} new papers — {date}"
+/// greeting = "Hello,\n\n{count} new papers were found.\n\n"
+/// paper = "{title}\n  {journal}\n  {href}\n\n"
+/// footer = "\nSent by linkdrive-rs.\n"
+/// ```
+/// "subject", "greeting" and "footer" take "{count}" and "{date}", and
+/// "subject" also takes "{keywords}", the keywords of the papers. "paper"
+/// takes "{title}", "{journal}", "{href}" and "{keyword}", and must contain
+/// "{title}" and "{href}".
+pub struct Template {
+    subject: String,
+    greeting: String,
+    paper: String,
+    footer: String,
+}
+
+impl Default for Template {
+    /// The built-in English template.
+    fn default() -> Self {
+        Self {
+            subject: "{count} new papers — {date}".into(),
+            greeting: "Hello,\n\n{count} new papers were found.\n\n".into(),
+            paper: "{title}\n  {journal}\n  {href}\n\n".into(),
+            footer: "\nSent by linkdrive-rs.\n".into(),
+        }
+    }
+}
+
+impl Template {
+    /// Loads the template file at "path". A missing file falls back to the
+    /// built-in English template.
+    pub fn load(path: &str) -> Result<Self, Exception> {
+        if !Path::new(path).exists() {
+            tracing::warn!("Template '{}' not found, using the built-in template", path);
+            return Ok(Self::default());
+        }
+
+        let config = Config::builder()
+            .add_source(config::File::with_name(path))
+            .build()?;
+        let default = Self::default();
+        let template = Self {
+            subject: config.get_string("subject").unwrap_or(default.subject),
+            greeting: config.get_string("greeting").unwrap_or(default.greeting),
+            paper: config.get_string("paper").unwrap_or(default.paper),
+            footer: config.get_string("footer").unwrap_or(default.footer),
+        };
+
+        // Every paper needs at least its title and link.
+        for placeholder in ["{title}", "{href}"] {
+            if !template.paper.contains(placeholder) {
+                let message = format!("'paper' in '{}' is missing {}.", path, placeholder);
+                return Err(Box::new(TemplateException(message)));
+            }
+        }
+        Ok(template)
+    }
+
+    /// Replaces the subject of the template, as "subject_template" in
+    /// Settings.toml does.
+    pub fn set_subject(&mut self, subject: String) {
+        self.subject = subject;
+    }
+
+    pub fn render_subject(&self, count: usize, date: &str, keywords: &str) -> String {
+        let count = count.to_string();
+        render(
+            &self.subject,
+            &[
+                ("{count}", count.as_str()),
+                ("{date}", date),
+                ("{keywords}", keywords),
+            ],
+        )
+    }
+
+    /// Renders the greeting, the papers and the footer.
+    pub fn render_body(
+        &self,
+        papers: &[Paper],
+        max_entries: Option<usize>,
+        count: usize,
+        date: &str,
+    ) -> String {
+        let count = count.to_string();
+        let values = [("{count}", count.as_str()), ("{date}", date)];
+        let mut body = render(&self.greeting, &values);
+        body.push_str(&build_body(papers, max_entries, |paper| {
+            self.render_paper(paper)
+        }));
+        body.push_str(&render(&self.footer, &values));
+        body
+    }
+
+    /// Renders the greeting, the first "max_entries" papers as blocks of
+    /// plain text and the footer, for an email without the html and the
+    /// attachments.
+    pub fn render_plaintext(
+        &self,
+        papers: &[Paper],
+        max_entries: Option<usize>,
+        date: &str,
+    ) -> String {
+        let count = papers.len().to_string();
+        let values = [("{count}", count.as_str()), ("{date}", date)];
+        let mut body = render(&self.greeting, &values);
+        let shown = max_entries.unwrap_or(papers.len()).min(papers.len());
+        body.extend(papers[..shown].iter().map(plaintext_entry));
+        if shown < papers.len() {
+            let _ = writeln!(body, "…and {} more", papers.len() - shown);
+        }
+        body.push_str(&render(&self.footer, &values));
+        body
+    }
+
+    fn render_paper(&self, paper: &Paper) -> String {
+        render(
+            &self.paper,
+            &[
+                ("{title}", paper.title.as_str()),
+                ("{journal}", paper.journal.as_str()),
+                ("{href}", paper.href.as_str()),
+                ("{keyword}", paper.keyword.as_str()),
+            ],
+        )
+    }
+}
+
+/// A paper as "Title\n  Journal | Date\n  href\n" in a plain text body.
+fn plaintext_entry(paper: &Paper) -> String {
+    format!(
+        "{}\n  {} | {}\n  {}\n\n",
+        paper.title,
+        paper.journal,
+        paper.published.as_deref().unwrap_or("-"),
+        paper.href
+    )
+}
+
+/// Replaces each placeholder in "text" with its value.
+fn render(text: &str, values: &[(&str, &str)]) -> String {
+    values
+        .iter()
+        .fold(text.to_string(), |text, (placeholder, value)| {
+            text.replace(placeholder, value)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MatchLocation;
+
+    fn papers(count: usize) -> Vec<Paper> {
+        (0..count)
+            .map(|index| Paper {
+                keyword: "ai".to_string(),
+                title: format!("Paper {}", index),
+                authors: Vec::new(),
+                journal: "A journal".to_string(),
+                published: None,
+                href: format!("https://example.com/pii/{}", index),
+                doi: None,
+                abstract_text: None,
+                score: 0.0,
+                match_location: MatchLocation::Title,
+            })
+            .collect()
+    }
+
+    fn entry(paper: &Paper) -> String {
+        format!("{}\n", paper.title)
+    }
+
+    #[test]
+    fn body_lists_exactly_max_entries_without_a_note() {
+        let body = build_body(&papers(3), Some(3), entry);
+        assert_eq!(body, "Paper 0\nPaper 1\nPaper 2\n");
+    }
+
+    #[test]
+    fn body_notes_the_papers_over_max_entries() {
+        let body = build_body(&papers(4), Some(3), entry);
+        assert_eq!(
+            body,
+            "Paper 0\nPaper 1\nPaper 2\n…and 1 more (see attachment)\n"
+        );
+        let body = build_body(&papers(5), None, entry);
+        assert!(!body.contains("more"));
+    }
+
+    #[test]
+    fn html_lists_exactly_max_entries() {
+        let html = build_html("Heading", &papers(3), Some(3));
+        assert_eq!(html.matches("<tr><td>").count(), 3);
+        assert!(!html.contains("more"));
+
+        let html = build_html("Heading", &papers(5), Some(3));
+        assert_eq!(html.matches("<tr><td>").count(), 3);
+        assert!(html.contains("<p>…and 2 more (see attachment)</p>"));
+    }
+
+    #[test]
+    fn plaintext_honors_max_entries() {
+        let template = Template::default();
+        let body = template.render_plaintext(&papers(4), Some(3), "2024-01-01");
+        assert!(body.contains("Paper 2\n  A journal | -\n  https://example.com/pii/2\n"));
+        assert!(!body.contains("Paper 3"));
+        assert!(body.contains("…and 1 more\n"));
+
+        let body = template.render_plaintext(&papers(3), Some(3), "2024-01-01");
+        assert!(body.contains("Paper 2"));
+        assert!(!body.contains("more"));
+    }
+}
@@ -1,4 +1,5 @@
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    linkdrive_rs::run_app()?;
-    Ok(())
+use std::process::ExitCode;
+
+fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
+    linkdrive_rs::run_app()
 }
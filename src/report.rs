@@ -0,0 +1,107 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+
+use chrono::NaiveDateTime;
+
+use crate::load_meta_path;
+use crate::Exception;
+
+/// What happened in a single run, saved as "Papers.meta.json" next to the
+/// csv file with "write_run_metadata".
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SearchReport {
+    pub started_at: NaiveDateTime,
+    pub finished_at: Option<NaiveDateTime>,
+    pub keyword: Vec<String>,
+    /// The query url of each keyword.
+    pub queries: BTreeMap<String, String>,
+    /// The number of papers on the result page of each keyword.
+    pub found: BTreeMap<String, usize>,
+    /// The number of papers not seen before for each keyword.
+    pub new: BTreeMap<String, usize>,
+    /// The error of each keyword that failed to be searched.
+    pub failures: BTreeMap<String, String>,
+}
+
+impl SearchReport {
+    pub fn new(keyword: &HashSet<String>, started_at: NaiveDateTime) -> Self {
+        let mut keyword = keyword.iter().cloned().collect::<Vec<String>>();
+        keyword.sort();
+        Self {
+            started_at,
+            finished_at: None,
+            keyword,
+            queries: BTreeMap::new(),
+            found: BTreeMap::new(),
+            new: BTreeMap::new(),
+            failures: BTreeMap::new(),
+        }
+    }
+
+    /// Logs a line for each keyword, so a keyword that stops finding
+    /// anything stands out.
+    pub fn log_summary(&self) {
+        for keyword in &self.keyword {
+            match (self.new.get(keyword), self.failures.get(keyword)) {
+                (Some(new), _) => tracing::info!("keyword \"{}\": {} new", keyword, new),
+                (None, Some(_)) => tracing::info!("keyword \"{}\": failed", keyword),
+                (None, None) => {}
+            }
+        }
+    }
+
+    /// Overwrites "Papers.meta.json" with this run.
+    pub fn save(&self) -> Result<(), Exception> {
+        fs::write(load_meta_path()?, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: u32, minute: u32) -> NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn report() -> SearchReport {
+        let keyword = HashSet::from(["battery".to_string(), "ai".to_string()]);
+        let mut report = SearchReport::new(&keyword, at(8, 30));
+        report.finished_at = Some(at(8, 32));
+        let query = "https://www.sciencedirect.com/search?qs=ai&show=25&sortBy=date";
+        report.queries.insert("ai".into(), query.into());
+        report.found.insert("ai".into(), 25);
+        report.new.insert("ai".into(), 3);
+        report.failures.insert("battery".into(), "timed out".into());
+        report
+    }
+
+    #[test]
+    fn meta_json_has_the_counts_of_each_keyword() {
+        let json = serde_json::to_value(report()).unwrap();
+        assert_eq!(json["started_at"], "2024-01-01T08:30:00");
+        assert_eq!(json["finished_at"], "2024-01-01T08:32:00");
+        assert_eq!(json["keyword"], serde_json::json!(["ai", "battery"]));
+        assert_eq!(
+            json["queries"]["ai"],
+            "https://www.sciencedirect.com/search?qs=ai&show=25&sortBy=date"
+        );
+        assert_eq!(json["found"], serde_json::json!({ "ai": 25 }));
+        assert_eq!(json["new"], serde_json::json!({ "ai": 3 }));
+        assert_eq!(
+            json["failures"],
+            serde_json::json!({ "battery": "timed out" })
+        );
+    }
+
+    #[test]
+    fn meta_json_round_trips() {
+        let bytes = serde_json::to_vec_pretty(&report()).unwrap();
+        let parsed: SearchReport = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(parsed, report());
+    }
+}
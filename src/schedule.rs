@@ -0,0 +1,208 @@
+use std::collections::HashSet;
+
+use chrono::prelude::*;
+use chrono::Duration;
+
+/// When a keyword is searched.
+#[derive(Clone, PartialEq)]
+pub enum Schedule {
+    /// On every one of "weekdays" at every "hour:minute" of "times".
+    Weekly {
+        weekdays: HashSet<Weekday>,
+        times: Vec<(u32, u32)>,
+    },
+    /// Every "hours" hours since the last search.
+    Interval { hours: u32 },
+}
+
+impl Schedule {
+    /// Returns true when the keyword should be searched at "now", given the
+    /// time it was last searched.
+    ///
+    /// A weekly slot stays open for "grace_minutes" after the scheduled
+    /// minute, so a slow iteration of the main loop does not step over it.
+    /// It fires only once, as a search after the slot closes it, while the
+    /// next slot of the same day fires again.
+    pub fn is_due(
+        &self,
+        now: NaiveDateTime,
+        last_run: Option<NaiveDateTime>,
+        grace_minutes: u32,
+    ) -> bool {
+        match self {
+            Schedule::Weekly { .. } => {
+                let slot = match self.previous_slots(now).max() {
+                    Some(slot) => slot,
+                    None => return false,
+                };
+                let in_grace = now - slot < Duration::minutes(1 + grace_minutes as i64);
+                let fired = match last_run {
+                    Some(last_run) => last_run >= slot,
+                    None => false,
+                };
+                in_grace && !fired
+            }
+            Schedule::Interval { hours } => match last_run {
+                Some(last_run) => now - last_run >= Duration::hours(*hours as i64),
+                None => true,
+            },
+        }
+    }
+
+    /// The next time the keyword is due at or after "now". It is "now"
+    /// itself when the keyword is already due.
+    pub fn next_run(
+        &self,
+        now: NaiveDateTime,
+        last_run: Option<NaiveDateTime>,
+        grace_minutes: u32,
+    ) -> NaiveDateTime {
+        if self.is_due(now, last_run, grace_minutes) {
+            return now;
+        }
+        match self {
+            // Every slot comes back a week after its previous one.
+            Schedule::Weekly { .. } => self
+                .previous_slots(now)
+                .map(|slot| slot + Duration::days(7))
+                .min()
+                .unwrap_or(now),
+            Schedule::Interval { hours } => match last_run {
+                Some(last_run) => last_run + Duration::hours(*hours as i64),
+                None => now,
+            },
+        }
+    }
+
+    /// Returns true when a weekly slot earlier today has passed without a
+    /// search since, as when the crawler was down at the time.
+    pub fn missed_today(&self, now: NaiveDateTime, last_run: Option<NaiveDateTime>) -> bool {
+        let slot = match self
+            .previous_slots(now)
+            .filter(|slot| slot.date() == now.date())
+            .max()
+        {
+            Some(slot) => slot,
+            None => return false,
+        };
+        match last_run {
+            Some(last_run) => last_run < slot,
+            None => true,
+        }
+    }
+
+    /// The latest time of each slot at or before "now".
+    fn previous_slots(&self, now: NaiveDateTime) -> impl Iterator<Item = NaiveDateTime> + '_ {
+        let weekly = match self {
+            Schedule::Weekly { weekdays, times } => Some((weekdays, times)),
+            Schedule::Interval { .. } => None,
+        };
+        weekly.into_iter().flat_map(move |(weekdays, times)| {
+            weekdays.iter().flat_map(move |weekday| {
+                times
+                    .iter()
+                    .map(move |(hour, minute)| previous_slot(now, *weekday, *hour, *minute))
+            })
+        })
+    }
+}
+
+/// The latest "weekday" at "hour:minute" at or before "now".
+fn previous_slot(now: NaiveDateTime, weekday: Weekday, hour: u32, minute: u32) -> NaiveDateTime {
+    let days_back = (7 + now.weekday().num_days_from_monday() - weekday.num_days_from_monday()) % 7;
+    let slot = (now.date() - Duration::days(days_back as i64))
+        .and_hms_opt(hour, minute, 0)
+        .unwrap();
+    if slot > now {
+        slot - Duration::days(7)
+    } else {
+        slot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        // 2024-01-01 is a Monday.
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn monday_at_nine() -> Schedule {
+        Schedule::Weekly {
+            weekdays: HashSet::from([Weekday::Mon]),
+            times: vec![(9, 0)],
+        }
+    }
+
+    #[test]
+    fn due_at_the_exact_minute() {
+        assert!(monday_at_nine().is_due(at(1, 9, 0), None, 2));
+    }
+
+    #[test]
+    fn due_inside_the_grace_window_once() {
+        let schedule = monday_at_nine();
+        assert!(schedule.is_due(at(1, 9, 2), None, 2));
+        assert!(schedule.is_due(at(1, 9, 2), Some(at(1, 8, 59)), 2));
+        assert!(!schedule.is_due(at(1, 9, 2), Some(at(1, 9, 0)), 2));
+    }
+
+    #[test]
+    fn not_due_past_the_grace_window() {
+        let schedule = monday_at_nine();
+        assert!(!schedule.is_due(at(1, 9, 3), None, 2));
+        assert!(!schedule.is_due(at(1, 8, 59), None, 2));
+    }
+
+    #[test]
+    fn next_weekly_run_is_the_coming_slot() {
+        let schedule = monday_at_nine();
+        assert_eq!(schedule.next_run(at(1, 8, 0), None, 2), at(1, 9, 0));
+        assert_eq!(schedule.next_run(at(1, 9, 1), None, 2), at(1, 9, 1));
+    }
+
+    #[test]
+    fn next_weekly_run_after_the_slot_of_today() {
+        let schedule = monday_at_nine();
+        assert_eq!(
+            schedule.next_run(at(1, 10, 0), Some(at(1, 9, 0)), 2),
+            at(8, 9, 0)
+        );
+        assert_eq!(schedule.next_run(at(1, 10, 0), None, 2), at(8, 9, 0));
+    }
+
+    #[test]
+    fn next_interval_run_follows_the_last_run() {
+        let schedule = Schedule::Interval { hours: 24 };
+        assert_eq!(
+            schedule.next_run(at(1, 10, 0), Some(at(1, 9, 0)), 2),
+            at(2, 9, 0)
+        );
+        assert_eq!(schedule.next_run(at(1, 10, 0), None, 2), at(1, 10, 0));
+        assert_eq!(
+            schedule.next_run(at(3, 10, 0), Some(at(1, 9, 0)), 2),
+            at(3, 10, 0)
+        );
+    }
+
+    #[test]
+    fn slot_of_today_is_missed_until_searched() {
+        let schedule = monday_at_nine();
+        assert!(schedule.missed_today(at(1, 9, 5), None));
+        assert!(schedule.missed_today(at(1, 9, 5), Some(at(1, 8, 0))));
+        assert!(!schedule.missed_today(at(1, 9, 5), Some(at(1, 9, 0))));
+    }
+
+    #[test]
+    fn slot_of_another_day_is_not_missed() {
+        let schedule = monday_at_nine();
+        assert!(!schedule.missed_today(at(1, 8, 55), None));
+        assert!(!schedule.missed_today(at(2, 9, 5), None));
+        assert!(!Schedule::Interval { hours: 24 }.missed_today(at(1, 9, 5), None));
+    }
+}
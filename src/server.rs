@@ -0,0 +1,134 @@
+use std::fs;
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::crawler::ChromeDriver;
+use crate::{load_status_path, Exception};
+
+/// Starts the control server on a background thread.
+///
+/// "POST /run" runs a one-shot search and responds with the new papers as
+/// JSON. The crawler is locked for the whole search, so concurrent triggers
+/// and the scheduled run are served one at a time. "GET /status" responds
+/// with the saved "status.json".
+pub fn spawn(
+    crawler: Arc<Mutex<ChromeDriver>>,
+    address: SocketAddr,
+    token: Option<String>,
+) -> Result<(), Exception> {
+    let server = Server::http(address)?;
+    tracing::info!("Control server listening on {}", address);
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = handle(&crawler, &request, token.as_deref());
+            if let Err(e) = request.respond(response) {
+                tracing::warn!("Failed to respond to the control request: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn handle(
+    crawler: &Mutex<ChromeDriver>,
+    request: &Request,
+    token: Option<&str>,
+) -> Response<Cursor<Vec<u8>>> {
+    let path = request_path(request.url());
+    let run = *request.method() == Method::Post && path == "/run";
+    let status = *request.method() == Method::Get && path == "/status";
+    if !run && !status {
+        return json_response(404, error_body("not found"));
+    }
+
+    // Compare the bearer token when it is set, in constant time so the
+    // token can not be guessed from the time of the response.
+    if let Some(token) = token {
+        let bearer = format!("Bearer {}", token);
+        let authorized = request.headers().iter().any(|header| {
+            header.field.equiv("Authorization")
+                && constant_time_eq(header.value.as_str().as_bytes(), bearer.as_bytes())
+        });
+        if !authorized {
+            return json_response(401, error_body("unauthorized"));
+        }
+    }
+
+    // The status is read from the file, so it does not wait for a search
+    // holding the crawler.
+    if status {
+        return match load_status_path().and_then(|path| Ok(fs::read_to_string(path)?)) {
+            Ok(body) => json_response(200, body),
+            Err(e) => json_response(503, error_body(&e.to_string())),
+        };
+    }
+
+    tracing::info!("Run triggered from the control server");
+    let mut crawler = crawler.lock().unwrap_or_else(|e| e.into_inner());
+    let keyword = crawler.keyword_from_settings();
+    match crawler.search(keyword) {
+        Ok(papers) => match serde_json::to_string(&papers) {
+            Ok(body) => json_response(200, body),
+            Err(e) => json_response(500, error_body(&e.to_string())),
+        },
+        Err(e) => json_response(500, error_body(&e.to_string())),
+    }
+}
+
+/// Compares the bytes without stopping at the first difference. Only the
+/// length is told apart early.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn error_body(message: &str) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
+
+fn json_response(status: u16, body: String) -> Response<Cursor<Vec<u8>>> {
+    let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+    Response::from_string(body)
+        .with_status_code(status)
+        .with_header(content_type)
+}
+
+/// The path of the request url without the query string and a trailing
+/// slash, so "/run?x=1" and "/run/" are "/run".
+fn request_path(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    match path.trim_end_matches('/') {
+        "" => "/",
+        path => path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_drops_the_query_and_the_trailing_slash() {
+        assert_eq!(request_path("/run"), "/run");
+        assert_eq!(request_path("/run?x=1"), "/run");
+        assert_eq!(request_path("/run/"), "/run");
+        assert_eq!(request_path("/status/?verbose#top"), "/status");
+        assert_eq!(request_path("/"), "/");
+        assert_eq!(request_path("/running"), "/running");
+    }
+
+    #[test]
+    fn compares_tokens() {
+        assert!(constant_time_eq(b"Bearer secret", b"Bearer secret"));
+        assert!(!constant_time_eq(b"Bearer secret", b"Bearer secreT"));
+        assert!(!constant_time_eq(b"Bearer secret", b"Bearer secret!"));
+        assert!(!constant_time_eq(b"", b"Bearer secret"));
+    }
+}
@@ -0,0 +1,590 @@
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use chrono::prelude::*;
+use headless_chrome::{Element, Tab};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use rayon::prelude::*;
+
+use crate::errors::{BlockedException, ParseException};
+use crate::storage::{MatchLocation, Paper, SearchField, SortBy};
+use crate::Exception;
+
+/// The words in the title of the page served instead of the results when
+/// the site throttles the crawler, in lowercase.
+const BLOCKED_TITLES: [&str; 3] = ["are you a robot", "access denied", "just a moment"];
+
+/// The elements of a captcha or an interstitial page.
+const BLOCKED_SELECTOR: &str = "#captcha-container, iframe[src*='captcha'], #challenge-form";
+
+/// The message of a search without results, shown in place of the list.
+const NO_RESULTS_SELECTOR: &str = ".error-zero-results, .search-body-no-results";
+
+/// Everything but the unreserved characters of a url is percent-encoded.
+const QUERY_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// A site to search for papers on. The crawler loads the query of each
+/// keyword and hands the loaded page to the source to parse, so a new site
+/// only needs a new implementation of this trait.
+pub trait SearchSource: Send {
+    /// The root url of the site, where robots.txt is read from.
+    fn domain(&self) -> &str;
+
+    /// The url of the "page"th result page of the keyword, counting from 0,
+    /// with "per_page" results on a page, newest first unless the source is
+    /// set to sort otherwise. When "since" is set, the results are limited
+    /// to the papers from then up to "today" in the configured time zone.
+    fn build_query(
+        &self,
+        keyword: &str,
+        since: Option<NaiveDate>,
+        today: NaiveDate,
+        page: usize,
+        per_page: usize,
+    ) -> String;
+
+    /// The number of results on a full page.
+    fn results_per_page(&self) -> usize;
+
+    /// The smallest page size the source offers for "limit" results, or
+    /// the largest one.
+    fn page_size(&self, limit: usize) -> usize;
+
+    /// Waits at most "timeout" for each element of the result page of
+    /// "per_page" results loaded on the tab and parses the papers on it.
+    fn parse_results(
+        &self,
+        tab: &Tab,
+        keyword: &str,
+        journal_placeholder: &str,
+        per_page: usize,
+        timeout: Duration,
+    ) -> Result<Vec<Paper>, Exception>;
+
+    /// The selector of the abstract on the page of a paper.
+    fn abstract_selector(&self) -> &str;
+
+    /// The selector of the "Accept" button of the cookie consent banner.
+    fn consent_selector(&self) -> &str;
+}
+
+/// # ScienceDirect
+///
+/// https://www.sciencedirect.com
+pub struct ScienceDirect {
+    domain_string: String,
+    base_query_string: String,
+    sort_by: SortBy,
+    max_indices_per_page: usize,
+}
+
+impl ScienceDirect {
+    /// ScienceDirect shows 25, 50 or 100 results on a page. The keyword is
+    /// searched for in the full text with "qs=", or in the titles only with
+    /// "title=". The results are sorted with "sortBy=date" or
+    /// "sortBy=relevance".
+    ///
+    /// "base_url" is "https://www.sciencedirect.com/" but for a mirror or a
+    /// local copy of the site in a test.
+    pub fn new(
+        base_url: &str,
+        results_per_page: usize,
+        search_field: SearchField,
+        sort_by: SortBy,
+    ) -> Self {
+        let domain_string = format!("{}/", base_url.trim_end_matches('/'));
+        let base_query_string = match search_field {
+            SearchField::All => format!("{}search?qs=", domain_string),
+            SearchField::Title => format!("{}search?title=", domain_string),
+        };
+        Self {
+            domain_string,
+            base_query_string,
+            sort_by,
+            max_indices_per_page: results_per_page,
+        }
+    }
+}
+
+impl SearchSource for ScienceDirect {
+    fn domain(&self) -> &str {
+        &self.domain_string
+    }
+
+    /// Adds a new keyword to search for. When "since" is set, the query is
+    /// limited to the years from "since" up to the year of "today", as
+    /// ScienceDirect only filters by year. The pages after the first are
+    /// reached by the offset of their first result.
+    fn build_query(
+        &self,
+        keyword: &str,
+        since: Option<NaiveDate>,
+        today: NaiveDate,
+        page: usize,
+        per_page: usize,
+    ) -> String {
+        // Split keyword argument at whitespaces into a token vector.
+        let token = keyword
+            .split_ascii_whitespace()
+            .map(String::from)
+            .collect::<Vec<String>>();
+
+        // Join tokens with a single space and percent-encode the whole
+        // keyword, so "&", "+" and non-ASCII characters stay in it.
+        let search_keyword = utf8_percent_encode(&token.join(" "), QUERY_ENCODE_SET).to_string();
+
+        // Build a query string from joining "self.base_query_string" and
+        // the search keyword.
+        let mut query = String::from(&self.base_query_string);
+        query.push_str(&search_keyword);
+        let _ = write!(&mut query, "&show={}", per_page);
+        query.push_str(match self.sort_by {
+            SortBy::Date => "&sortBy=date",
+            SortBy::Relevance => "&sortBy=relevance",
+        });
+        if page > 0 {
+            let _ = write!(&mut query, "&offset={}", page * per_page);
+        }
+        if let Some(since) = since {
+            let _ = write!(&mut query, "&date={}-{}", since.year(), today.year());
+        }
+        query
+    }
+
+    fn results_per_page(&self) -> usize {
+        self.max_indices_per_page
+    }
+
+    /// ScienceDirect shows 25, 50 or 100 results on a page.
+    fn page_size(&self, limit: usize) -> usize {
+        match [25, 50, 100].into_iter().find(|size| *size >= limit) {
+            Some(size) => size,
+            None => 100,
+        }
+    }
+
+    fn parse_results(
+        &self,
+        tab: &Tab,
+        keyword: &str,
+        journal_placeholder: &str,
+        per_page: usize,
+        timeout: Duration,
+    ) -> Result<Vec<Paper>, Exception> {
+        let outer_selector = "#srp-results-list";
+        let result_selector = format!("{} > ol > li", outer_selector);
+
+        // A throttled crawler gets a captcha instead of the results, which
+        // is not the same as finding nothing.
+        let title = tab.get_title().unwrap_or_default();
+        if is_blocked_title(&title) || tab.find_element(BLOCKED_SELECTOR).is_ok() {
+            let message = format!("The site blocked the search of '{}': '{}'.", keyword, title);
+            return Err(Box::new(BlockedException(message)));
+        }
+
+        // A keyword without results gets a message instead of the list.
+        let either = format!("{}, {}", outer_selector, NO_RESULTS_SELECTOR);
+        tab.wait_for_element_with_custom_timeout(&either, timeout)?;
+
+        // The list is created before its results are rendered, so it is
+        // only empty once the time is up. It is parsed as soon as it is
+        // full, or as soon as it has results and the page has loaded, as
+        // the last page is rarely full.
+        let started = Instant::now();
+        loop {
+            if tab.find_element(NO_RESULTS_SELECTOR).is_ok() {
+                tracing::info!("No results for '{}'", keyword);
+                return Ok(Vec::new());
+            }
+            let count = tab.find_elements(&result_selector).map_or(0, |li| li.len());
+            if count >= per_page || (count > 0 && !is_loading(tab)) || started.elapsed() >= timeout
+            {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        let result_list = tab.wait_for_element_with_custom_timeout(outer_selector, timeout)?;
+        let li_list = match result_list.wait_for_elements("li") {
+            Ok(li_list) => li_list,
+            Err(_) => {
+                tracing::info!("No results for '{}'", keyword);
+                return Ok(Vec::new());
+            }
+        };
+
+        // Parallel parse() execution.
+        self.parse(li_list, keyword, journal_placeholder)
+    }
+
+    fn abstract_selector(&self) -> &str {
+        "div.abstract.author"
+    }
+
+    fn consent_selector(&self) -> &str {
+        "#onetrust-accept-btn-handler"
+    }
+}
+
+impl ScienceDirect {
+    /// Multi-threaded parser utilizing ["rayon"].
+    ///
+    /// A malformed item is logged and skipped. It fails only when every item
+    /// of the list has failed.
+    fn parse(
+        &self,
+        item_list: Vec<Element>,
+        keyword: &str,
+        journal_placeholder: &str,
+    ) -> Result<Vec<Paper>, Exception> {
+        // Parse items in the list. Errors are carried as strings, since
+        // "Exception" can not be sent across the threads. The results are
+        // collected in the order of the list, and the papers are recorded
+        // and written one by one afterwards.
+        let result_list = item_list
+            .par_iter()
+            .map(|item| {
+                Self::parse_item(item, keyword, &self.domain_string, journal_placeholder)
+                    .map_err(|e| e.to_string())
+            })
+            .collect::<Vec<Result<Option<Paper>, String>>>();
+
+        let mut paper_list = Vec::<Paper>::new();
+        let mut failure = 0;
+        for result in result_list {
+            match result {
+                Ok(Some(paper)) => paper_list.push(paper),
+                Ok(None) => {}
+                Err(e) => {
+                    tracing::warn!("Skipped a result of '{}': {}", keyword, e);
+                    failure += 1;
+                }
+            }
+        }
+        if failure > 0 && paper_list.is_empty() {
+            let message = format!("Failed to parse all {} results of '{}'.", failure, keyword);
+            return Err(Box::new(ParseException(message)));
+        }
+        if paper_list.is_empty() && !item_list.is_empty() {
+            tracing::warn!(
+                "No result of '{}' in {} items. The page layout may have changed.",
+                keyword,
+                item_list.len()
+            );
+        }
+        Ok(paper_list)
+    }
+
+    /// Parses a single item of the result list. Returns None for an item
+    /// that is not a result, such as an author or the download link.
+    fn parse_item(
+        item: &Element,
+        keyword: &str,
+        domain: &str,
+        journal_placeholder: &str,
+    ) -> Result<Option<Paper>, Exception> {
+        // The list also has the nested items of authors and links, which are
+        // told apart from the results by the class.
+        let attr = item.get_attributes()?.unwrap_or_default();
+        if !attribute(&attr, "class").map_or(false, |class| class.contains("ResultItem")) {
+            return Ok(None);
+        }
+
+        // The text of the result, read into strings so the paper is built
+        // without the browser.
+        let title_link = item.find_element("a.result-list-title-link").ok();
+        let (title, path) = match &title_link {
+            Some(title_link) => {
+                let link_attr = title_link.get_attributes()?.unwrap_or_default();
+                let path = attribute(&link_attr, "href").map(String::from);
+                (Some(title_link.get_inner_text()?), path)
+            }
+            None => (None, None),
+        };
+        let raw = RawResult {
+            title,
+            path,
+            journal: item
+                .find_element(".subtype-srctitle-link")
+                .and_then(|element| element.get_inner_text())
+                .ok(),
+            // Some results hide their authors.
+            authors: item
+                .find_elements(".Authors .author")
+                .map(|elements| {
+                    elements
+                        .iter()
+                        .filter_map(|element| element.get_inner_text().ok())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            // The date is the last span next to the journal.
+            published: item
+                .find_element(".srctitle-date-fields > span:last-child")
+                .and_then(|element| element.get_inner_text())
+                .ok(),
+            preview: item
+                .find_element(".preview-body")
+                .and_then(|element| element.get_inner_text())
+                .ok(),
+            doi_link: item
+                .find_element("a[href*='doi.org']")
+                .and_then(|element| element.get_content())
+                .ok(),
+        };
+        paper_from_result(raw, keyword, domain, journal_placeholder).map(Some)
+    }
+}
+
+/// The text of a single result, as read from the result page.
+#[derive(Default)]
+struct RawResult {
+    /// The text of the title link.
+    title: Option<String>,
+    /// The href of the title link, relative to the domain.
+    path: Option<String>,
+    journal: Option<String>,
+    authors: Vec<String>,
+    published: Option<String>,
+    /// The short preview of the abstract.
+    preview: Option<String>,
+    /// The html of the DOI link.
+    doi_link: Option<String>,
+}
+
+/// Builds the paper from the text of a result. Results without a journal
+/// (conference papers, book chapters) get "journal_placeholder" instead,
+/// and the ones without a date or authors are kept as they are.
+fn paper_from_result(
+    raw: RawResult,
+    keyword: &str,
+    domain: &str,
+    journal_placeholder: &str,
+) -> Result<Paper, Exception> {
+    // A result without its title link means the page layout has changed.
+    let title = match raw.title {
+        Some(title) => title,
+        None => return Err(Box::new(ParseException("No title link.".into()))),
+    };
+    let path = match raw.path {
+        Some(path) => path,
+        None => {
+            let message = "No href in the title link.".to_string();
+            return Err(Box::new(ParseException(message)));
+        }
+    };
+
+    // The complete href, joined the same way as before so the papers seen
+    // so far keep their keys.
+    let mut href = String::from(domain);
+    href.push_str(&path);
+
+    // The pii in the href is not a DOI, so the DOI is only taken from the
+    // href itself or a DOI link when the result has one.
+    let doi = doi_from_text(&href).or_else(|| raw.doi_link.as_deref().and_then(doi_from_text));
+
+    let mut paper = Paper {
+        title,
+        authors: raw.authors,
+        href,
+        doi,
+        keyword: keyword.into(),
+        journal: raw
+            .journal
+            .unwrap_or_else(|| journal_placeholder.to_string()),
+        published: raw.published,
+        abstract_text: raw
+            .preview
+            .filter(|abstract_text| !abstract_text.trim().is_empty()),
+        score: 0.0,
+        match_location: MatchLocation::Neither,
+    };
+    paper.match_location = paper.locate_keyword();
+    Ok(paper)
+}
+
+/// Returns true when the page title is one of a captcha or an interstitial.
+fn is_blocked_title(title: &str) -> bool {
+    let title = title.to_lowercase();
+    BLOCKED_TITLES.iter().any(|marker| title.contains(marker))
+}
+
+/// Returns true while the document of the tab has not finished loading.
+fn is_loading(tab: &Tab) -> bool {
+    tab.evaluate("document.readyState", false)
+        .ok()
+        .and_then(|state| state.value)
+        .map_or(false, |state| state != "complete")
+}
+
+/// The value of the attribute in the [name, value, name, value, ..] list of
+/// [Element::get_attributes].
+fn attribute<'a>(attr: &'a [String], name: &str) -> Option<&'a str> {
+    attr.chunks(2)
+        .find(|pair| pair[0] == name)
+        .and_then(|pair| pair.get(1))
+        .map(String::as_str)
+}
+
+/// Finds the first DOI ("10.<registrant>/<suffix>") in the text.
+fn doi_from_text(text: &str) -> Option<String> {
+    let is_end = |c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '<' | '>' | '?' | '#');
+    let mut rest = text;
+    while let Some(start) = rest.find("10.") {
+        let candidate = &rest[start..];
+        let candidate = &candidate[..candidate.find(is_end).unwrap_or(candidate.len())];
+        if let Some((registrant, suffix)) = candidate[3..].split_once('/') {
+            let valid_registrant = !registrant.is_empty()
+                && registrant.chars().all(|c| c.is_ascii_digit() || c == '.');
+            if valid_registrant && !suffix.is_empty() {
+                return Some(candidate.to_string());
+            }
+        }
+        rest = &rest[start + 3..];
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOMAIN: &str = "https://www.sciencedirect.com/";
+
+    /// A result as captured from a ScienceDirect result page.
+    fn captured_result() -> RawResult {
+        RawResult {
+            title: Some("A survey of supply chain resilience".into()),
+            path: Some("science/article/pii/S0925527323001234".into()),
+            journal: Some("International Journal of Production Economics".into()),
+            authors: vec!["Jane Doe".into(), "John Smith".into()],
+            published: Some("June 2023".into()),
+            preview: Some("Supply chains have been disrupted by ..".into()),
+            doi_link: Some("https://doi.org/10.1016/j.ijpe.2023.108912".into()),
+        }
+    }
+
+    #[test]
+    fn query_encodes_ampersands() {
+        let source = ScienceDirect::new(DOMAIN, 25, SearchField::All, SortBy::Date);
+        let today = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let query = source.build_query("research & development", None, today, 0, 25);
+        assert_eq!(
+            query,
+            "https://www.sciencedirect.com/search?qs=research%20%26%20development&show=25&sortBy=date"
+        );
+    }
+
+    #[test]
+    fn query_encodes_plus_and_accented_characters() {
+        let source = ScienceDirect::new(DOMAIN, 25, SearchField::Title, SortBy::Relevance);
+        let today = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let query = source.build_query("  c++   café ", None, today, 0, 25);
+        assert!(
+            query.starts_with("https://www.sciencedirect.com/search?title=c%2B%2B%20caf%C3%A9&")
+        );
+    }
+
+    #[test]
+    fn query_limits_the_years_up_to_today() {
+        let source = ScienceDirect::new(DOMAIN, 25, SearchField::All, SortBy::Date);
+        let since = NaiveDate::from_ymd_opt(2022, 12, 25).unwrap();
+        let today = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let query = source.build_query("graphene", Some(since), today, 0, 25);
+        assert!(query.ends_with("&date=2022-2024"), "{}", query);
+    }
+
+    #[test]
+    fn page_size_fits_the_limit() {
+        let source = ScienceDirect::new(DOMAIN, 25, SearchField::All, SortBy::Date);
+        assert_eq!(source.page_size(10), 25);
+        assert_eq!(source.page_size(50), 50);
+        assert_eq!(source.page_size(60), 100);
+        assert_eq!(source.page_size(250), 100);
+    }
+
+    #[test]
+    fn captcha_titles_are_blocked() {
+        assert!(is_blocked_title("Are you a robot?"));
+        assert!(is_blocked_title("Just a moment..."));
+        assert!(is_blocked_title("Access Denied"));
+        assert!(!is_blocked_title(
+            "ScienceDirect Search Results - supply chain"
+        ));
+    }
+
+    #[test]
+    fn parses_a_valid_result() {
+        let paper = paper_from_result(captured_result(), "supply chain", DOMAIN, "").unwrap();
+        assert_eq!(paper.title, "A survey of supply chain resilience");
+        assert_eq!(
+            paper.href,
+            "https://www.sciencedirect.com/science/article/pii/S0925527323001234"
+        );
+        assert_eq!(
+            paper.journal,
+            "International Journal of Production Economics"
+        );
+        assert_eq!(paper.authors, vec!["Jane Doe", "John Smith"]);
+        assert_eq!(paper.published.as_deref(), Some("June 2023"));
+        assert_eq!(paper.doi.as_deref(), Some("10.1016/j.ijpe.2023.108912"));
+        assert_eq!(paper.keyword, "supply chain");
+        assert_eq!(paper.match_location, MatchLocation::Title);
+    }
+
+    #[test]
+    fn missing_journal_takes_the_placeholder() {
+        let raw = RawResult {
+            journal: None,
+            ..captured_result()
+        };
+        let paper = paper_from_result(raw, "supply chain", DOMAIN, "N/A").unwrap();
+        assert_eq!(paper.journal, "N/A");
+    }
+
+    #[test]
+    fn missing_date_is_kept_undated() {
+        let raw = RawResult {
+            published: None,
+            ..captured_result()
+        };
+        let paper = paper_from_result(raw, "supply chain", DOMAIN, "").unwrap();
+        assert_eq!(paper.published, None);
+        assert_eq!(paper.published_date(), None);
+    }
+
+    #[test]
+    fn missing_title_link_is_an_error() {
+        let raw = RawResult {
+            title: None,
+            path: None,
+            ..captured_result()
+        };
+        assert!(paper_from_result(raw, "supply chain", DOMAIN, "").is_err());
+    }
+
+    #[test]
+    fn blank_preview_is_no_abstract() {
+        let raw = RawResult {
+            preview: Some("  ".into()),
+            ..captured_result()
+        };
+        let paper = paper_from_result(raw, "supply chain", DOMAIN, "").unwrap();
+        assert_eq!(paper.abstract_text, None);
+    }
+
+    #[test]
+    fn result_without_doi_link_has_no_doi() {
+        let raw = RawResult {
+            title: Some("Untitled".into()),
+            path: Some("science/article/pii/S0000000000000000".into()),
+            ..Default::default()
+        };
+        let paper = paper_from_result(raw, "ai", DOMAIN, "").unwrap();
+        assert_eq!(paper.doi, None);
+        assert!(paper.authors.is_empty());
+    }
+}
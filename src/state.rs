@@ -0,0 +1,122 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+use chrono::{Duration, NaiveDateTime};
+
+use crate::load_state_path;
+use crate::Exception;
+
+/// What the crawler keeps across restarts, saved as "state.json" next to
+/// the csv file.
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct State {
+    /// The last time each keyword was searched, whether it failed or not.
+    pub last_run: HashMap<String, NaiveDateTime>,
+    /// The last time each keyword was searched and reported without an
+    /// error, which the catch-up goes by.
+    pub last_success: HashMap<String, NaiveDateTime>,
+    /// The last time each paper was sent, by href.
+    pub notified: HashMap<String, NaiveDateTime>,
+    /// The keywords searched so far.
+    pub keyword: HashSet<String>,
+    /// The keyword of each paper seen so far, by href.
+    pub seen: HashMap<String, String>,
+    /// The last time a failed run was reported by email.
+    pub error_notified: Option<NaiveDateTime>,
+}
+
+impl State {
+    /// Loads the saved state. Starts empty when nothing is saved yet.
+    pub fn load() -> Result<Self, Exception> {
+        let state_path = load_state_path()?;
+        if !state_path.exists() {
+            return Ok(Self::default());
+        }
+        let mut state: Self = serde_json::from_slice(&fs::read(state_path)?)?;
+        state.migrate_last_success();
+        Ok(state)
+    }
+
+    /// A state saved before the successful runs were kept apart takes its
+    /// last runs for them.
+    fn migrate_last_success(&mut self) {
+        if self.last_success.is_empty() {
+            self.last_success = self.last_run.clone();
+        }
+    }
+
+    /// Returns true when the paper was sent within "hours" before "now".
+    pub fn notified_within(&self, href: &str, now: NaiveDateTime, hours: u32) -> bool {
+        match self.notified.get(href) {
+            Some(notified) => now - *notified < Duration::hours(hours as i64),
+            None => false,
+        }
+    }
+
+    /// Records the papers sent at "now" and forgets the ones sent more than
+    /// "hours" before.
+    pub fn record_notified<'a>(
+        &mut self,
+        href_list: impl Iterator<Item = &'a str>,
+        now: NaiveDateTime,
+        hours: u32,
+    ) {
+        self.notified
+            .retain(|_, notified| now - *notified < Duration::hours(hours as i64));
+        for href in href_list {
+            self.notified.insert(href.to_string(), now);
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Exception> {
+        fs::write(load_state_path()?, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(day: u32, hour: u32) -> NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn duplicate_inside_the_window_is_suppressed() {
+        let mut state = State::default();
+        state.record_notified(["10.1016/j.x.2024.1"].into_iter(), at(1, 9), 24);
+        assert!(state.notified_within("10.1016/j.x.2024.1", at(2, 8), 24));
+        assert!(!state.notified_within("10.1016/j.x.2024.2", at(2, 8), 24));
+    }
+
+    #[test]
+    fn old_state_takes_the_last_runs_as_successful() {
+        let mut state: State =
+            serde_json::from_str(r#"{ "last_run": { "graphene": "2024-01-01T09:00:00" } }"#)
+                .unwrap();
+        state.migrate_last_success();
+        assert_eq!(state.last_success.get("graphene"), Some(&at(1, 9)));
+
+        // A failed attempt afterwards leaves the last success as is.
+        state.last_run.insert("graphene".to_string(), at(2, 9));
+        state.migrate_last_success();
+        assert_eq!(state.last_success.get("graphene"), Some(&at(1, 9)));
+    }
+
+    #[test]
+    fn duplicate_outside_the_window_is_sent_again() {
+        let mut state = State::default();
+        state.record_notified(["10.1016/j.x.2024.1"].into_iter(), at(1, 9), 24);
+        assert!(!state.notified_within("10.1016/j.x.2024.1", at(2, 9), 24));
+
+        // A later record forgets the papers sent before the window.
+        state.record_notified(["10.1016/j.x.2024.2"].into_iter(), at(3, 9), 24);
+        assert!(!state.notified.contains_key("10.1016/j.x.2024.1"));
+        assert!(state.notified.contains_key("10.1016/j.x.2024.2"));
+    }
+}
@@ -0,0 +1,57 @@
+use std::fs;
+
+use chrono::NaiveDateTime;
+
+use crate::load_status_path;
+use crate::storage::Paper;
+use crate::Exception;
+
+/// Whether the crawler is alive and how its last run went, saved as
+/// "status.json" next to the csv file on every check of the schedule.
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Status {
+    /// The last time the schedule was checked.
+    pub updated_at: Option<NaiveDateTime>,
+    /// The last time a search was run.
+    pub last_run: Option<NaiveDateTime>,
+    /// The last time a search finished without an error.
+    pub last_success: Option<NaiveDateTime>,
+    /// The error of the last search, if it failed.
+    pub last_error: Option<String>,
+    /// The number of new papers found by the last search.
+    pub papers_last_run: usize,
+}
+
+impl Status {
+    /// Loads the saved status, so the last success survives a restart.
+    pub fn load() -> Result<Self, Exception> {
+        let status_path = load_status_path()?;
+        if !status_path.exists() {
+            return Ok(Self::default());
+        }
+        let status = serde_json::from_slice(&fs::read(status_path)?)?;
+        Ok(status)
+    }
+
+    /// Records the result of the search run at "now".
+    pub fn record_run(&mut self, now: NaiveDateTime, result: &Result<Vec<Paper>, Exception>) {
+        self.last_run = Some(now);
+        match result {
+            Ok(papers) => {
+                self.last_success = Some(now);
+                self.last_error = None;
+                self.papers_last_run = papers.len();
+            }
+            Err(e) => {
+                self.last_error = Some(e.to_string());
+                self.papers_last_run = 0;
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Exception> {
+        fs::write(load_status_path()?, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
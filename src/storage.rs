@@ -1,88 +1,341 @@
-use std::collections::{HashMap, HashSet};
-use std::env;
-use std::error::Error;
-use std::fmt::{Debug, Display};
-use std::fs::{self, File};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::Debug;
+use std::fs::{self, File, OpenOptions};
+use std::io;
 use std::mem;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::RwLock;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
 
 use chrono::prelude::*;
-use config::Config;
-use csv::Writer;
-use lettre::message::{header::ContentType, Attachment};
+use chrono_tz::Tz;
+use config::{Config, Map, Value};
+use csv::{QuoteStyle, Writer, WriterBuilder};
+use lettre::message::{header::ContentType, Attachment, Mailbox, MultiPart, SinglePart};
 use lettre::transport::smtp::authentication::Credentials;
-use lettre::{Message, SmtpTransport, Transport};
+use lettre::{Address, Message, SmtpTransport, Transport};
+use rust_xlsxwriter::Workbook;
 
-use crate::load_csv_path;
+use crate::database::Database;
+use crate::errors::{
+    EmailException, ProfileException, SettingsException, TimeFormatException, WeekdayException,
+};
+use crate::mail::{build_html, Template};
+use crate::schedule::Schedule;
+use crate::state::State;
 use crate::Exception;
+use crate::{load_csv_path, load_database_path, load_settings_path, DRY_RUN};
+
+/// The commented Settings.toml written when there is none.
+const SETTINGS_TEMPLATE: &str = include_str!("../Settings.toml");
+
+/// The keys of Settings.toml that have no default, by table.
+const REQUIRED_KEYS: [(&str, &str); 6] = [
+    ("default", "keyword"),
+    ("default", "email"),
+    ("default", "time"),
+    ("default", "weekday"),
+    ("profile", "id"),
+    ("profile", "password"),
+];
 
 pub struct Storage {
     keyword: RwLock<HashSet<String>>,
-    storage: RwLock<HashMap<String, Paper>>,
-    up_storage: RwLock<HashMap<String, Paper>>,
+    storage: RwLock<HashMap<String, String>>,
+    up_storage: RwLock<HashMap<String, String>>,
+    database: Option<Database>,
+    up_papers: RwLock<Vec<Paper>>,
     settings: RwLock<Settings>,
-    file_handle: RwLock<Writer<File>>,
-    counter: RwLock<u32>,
+    /// When Settings.toml was last modified as of the settings in use.
+    settings_modified: RwLock<Option<SystemTime>>,
+    file_handle: RwLock<Option<Writer<File>>>,
+    csv_path: RwLock<PathBuf>,
+    /// The papers written to the csv file since the last email.
+    counter: AtomicU32,
+    /// The emails still being sent.
+    sending: RwLock<Vec<JoinHandle<Result<(), String>>>>,
+    state: RwLock<State>,
 }
 
 impl Storage {
-    pub fn new() -> Self {
-        let keyword = HashSet::<String>::new();
-        let storage = HashMap::<String, Paper>::new();
-        let up_storage = HashMap::<String, Paper>::new();
-        let settings = Settings::new().unwrap();
-        let file_handle = Writer::from_path(load_csv_path().unwrap()).unwrap();
+    pub fn new() -> Result<Self, Exception> {
+        let settings_modified = settings_modified();
+        let settings = Settings::new()?;
+        let mut state = State::load().unwrap_or_else(|e| {
+            tracing::warn!("Failed to load the saved state: {}", e);
+            State::default()
+        });
 
-        Self {
+        // Pick up the papers seen before the restart, except the ones of
+        // the keywords removed from the settings in the meantime.
+        let (added, removed) = keyword_changes(&state.keyword, &settings.keyword);
+        if !state.keyword.is_empty() && !added.is_empty() {
+            tracing::info!("Keywords added since the last start: {:?}", added);
+        }
+        if !removed.is_empty() {
+            tracing::info!("Keywords removed since the last start: {:?}", removed);
+        }
+        let mut keyword = state.keyword.clone();
+        keyword.retain(|k| settings.keyword.contains(k));
+        let mut storage = state.seen.clone();
+        storage.retain(|_, k| settings.keyword.contains(k));
+        let up_storage = HashMap::<String, String>::new();
+        let database = match settings.storage_backend {
+            StorageBackend::Memory => None,
+            StorageBackend::Sqlite => {
+                // Move the papers kept in "state.json" before the database
+                // was turned on into it once, and keep only the database.
+                let database = Database::open(&load_database_path()?)?;
+                if !state.seen.is_empty() {
+                    database.insert_seen(&storage, now_in(settings.timezone))?;
+                    tracing::info!("Moved {} seen papers into the database", storage.len());
+                    state.seen.clear();
+                    state.save()?;
+                    storage.clear();
+                }
+                Some(database)
+            }
+        };
+
+        let me = Self {
             keyword: RwLock::new(keyword),
             storage: RwLock::new(storage),
             up_storage: RwLock::new(up_storage),
+            database,
+            up_papers: RwLock::new(Vec::new()),
             settings: RwLock::new(settings),
-            file_handle: RwLock::new(file_handle),
-            counter: RwLock::new(0),
+            settings_modified: RwLock::new(settings_modified),
+            file_handle: RwLock::new(None),
+            csv_path: RwLock::new(load_csv_path()?),
+            counter: AtomicU32::new(0),
+            sending: RwLock::new(Vec::new()),
+            state: RwLock::new(state),
+        };
+        Ok(me)
+    }
+
+    /// Looks the key up in the database with "storage_backend = sqlite",
+    /// or in the papers kept in "state.json" otherwise. A failed lookup is
+    /// an error, as taking the paper for a new one would send it again.
+    pub fn contains_key(&self, key: &str) -> Result<bool, Exception> {
+        match &self.database {
+            Some(database) => database.contains(key),
+            None => Ok(self.storage.read().unwrap().contains_key(key)),
         }
     }
 
-    pub fn contains_key(&self, key: &str) -> bool {
-        let reader = self.storage.read().unwrap();
-        reader.contains_key(key)
+    /// Returns true when the keyword has been searched before.
+    pub fn contains_keyword(&self, keyword: &str) -> bool {
+        let reader = self.keyword.read().unwrap();
+        reader.contains(keyword)
     }
 
     /// Write to the new storage which will later update the current one.
-    /// It takes a tuple argument consisting of ("keyword", "href") and
-    /// returns true if the new paper is uploaded.
-    pub fn insert(&self, key: (String, String), value: Paper) -> bool {
-        let (keyword, href) = key;
-        let mut writer = self.up_storage.write().unwrap();
-        writer.insert(href.to_string(), value);
-
-        // Only write to the file when the keyword has already been added,
-        // but the paper by the key is not in the hashmap.
-        let reader = self.keyword.read().unwrap();
-        !self.contains_key(&href) && reader.contains(&keyword)
+    /// The paper is keyed by its keyword and [Paper::dedup_key]. Look it up
+    /// with [Storage::is_new] before, as it is seen from the next run on.
+    pub fn insert(&self, paper: &Paper) {
+        let keyword = &paper.keyword;
+        let dedup_key = paper.dedup_key();
+        if self.database.is_some() {
+            self.up_papers.write().unwrap().push(paper.clone());
+        } else {
+            let mut writer = self.up_storage.write().unwrap();
+            writer.insert(dedup_key.to_string(), keyword.to_string());
+        }
+    }
+
+    /// Only write to the file when the keyword has already been added,
+    /// but the paper by the key has not been seen.
+    pub fn is_new(&self, paper: &Paper) -> Result<bool, Exception> {
+        if !self.contains_keyword(&paper.keyword) {
+            return Ok(false);
+        }
+        Ok(!self.contains_key(&paper.dedup_key())?)
     }
 
-    /// Utilizes [std::mem::take] and [std::mem::replace] to replace the
-    /// current value with the new value.
-    pub fn update(&self, new_keyword: HashSet<String>) {
-        let _ = mem::replace(&mut *self.keyword.write().unwrap(), new_keyword);
+    /// Utilizes [std::mem::take] to merge the papers found in this run
+    /// into the current storage. Keywords are searched on their own
+    /// schedules, so the papers of the other keywords are kept. Keywords
+    /// removed from the settings are dropped along with their papers.
+    ///
+    /// The result is saved to the state, so the papers seen so far are not
+    /// sent again after a restart.
+    pub fn update(&self, new_keyword: HashSet<String>) -> Result<(), Exception> {
+        let current_keyword = self.keyword_from_settings();
+
+        let mut keyword = self.keyword.write().unwrap();
+        let mut searched = keyword.clone();
+        searched.extend(new_keyword);
+        searched.retain(|k| current_keyword.contains(k));
+
+        // Only the papers of an added keyword are recorded without being
+        // sent, and the papers of a removed one are dropped.
+        let (added, removed) = keyword_changes(&keyword, &searched);
+        if !added.is_empty() {
+            tracing::info!("Keywords added, recorded without sending: {:?}", added);
+        }
+        if !removed.is_empty() {
+            tracing::info!("Keywords removed, their papers are dropped: {:?}", removed);
+        }
+        *keyword = searched;
 
         let new_storage = mem::take(&mut *self.up_storage.write().unwrap());
-        let _ = mem::replace(&mut *self.storage.write().unwrap(), new_storage);
+        let mut storage = self.storage.write().unwrap();
+        storage.extend(new_storage);
+        storage.retain(|_, k| current_keyword.contains(k));
+
+        if let Some(database) = &self.database {
+            let new_papers = mem::take(&mut *self.up_papers.write().unwrap());
+            database.insert(&new_papers, self.local_now())?;
+            database.retain_keywords(&current_keyword)?;
+        }
+
+        // The database keeps the papers seen with "storage_backend = sqlite".
+        let mut state = self.state.write().unwrap();
+        state.keyword = keyword.clone();
+        if self.database.is_none() {
+            state.seen = storage.clone();
+        }
+        state.save()
     }
 
-    /// Utilizes [std::mem::replace] to replace the current file handle
-    /// with the new one after sending an email.
+    /// Closes the file handle after sending an email. The file of the next
+    /// run is only opened on its first paper, so a search that records
+    /// nothing never truncates the csv file, and a timestamped file is named
+    /// after the time of that run. Such a search still leaves a csv file
+    /// with the header when there is none.
     pub fn new_file_handle(&self) -> Result<(), Exception> {
-        let new_file = Writer::from_path(load_csv_path()?)?;
-        let _ = mem::replace(&mut *self.file_handle.write().unwrap(), new_file);
+        if self.file_handle.read().unwrap().is_none() {
+            let csv_path = match self.csv_mode_from_settings() {
+                (Some(stamp), _) => timestamped_csv_path(stamp)?,
+                (None, _) => load_csv_path()?,
+            };
+            ensure_csv_header(&csv_path)?;
+        }
+        if let Some(mut writer) = mem::take(&mut *self.file_handle.write().unwrap()) {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// The time in the csv file name when it is timestamped, and whether
+    /// the csv file is appended to.
+    fn csv_mode_from_settings(&self) -> (Option<NaiveDateTime>, bool) {
+        let reader = self.settings.read().unwrap();
+        let append = reader.dry_run || reader.csv_mode == CsvMode::Append;
+        let stamp = match reader.timestamped_output {
+            true => Some(now_in(reader.timezone)),
+            false => None,
+        };
+        (stamp, append)
+    }
+
+    /// Opens the csv file of the run if it is not open yet. With
+    /// "csv_mode = append" the new papers keep being added to the same
+    /// file, and a dry run leaves the csv file of the last run as is.
+    fn ensure_file_handle(
+        &self,
+        stamp: Option<NaiveDateTime>,
+        append: bool,
+    ) -> Result<(), Exception> {
+        if self.file_handle.read().unwrap().is_some() {
+            return Ok(());
+        }
+        let csv_path = match stamp {
+            Some(stamp) => timestamped_csv_path(stamp)?,
+            None => load_csv_path()?,
+        };
+        self.open_file_handle(csv_path, append)
+    }
+
+    /// Opens the csv file and writes the header into a new one at once, so
+    /// the file has the header even when no paper is found.
+    fn open_file_handle(&self, csv_path: PathBuf, append: bool) -> Result<(), Exception> {
+        let (new_file, needs_header) = open_csv(&csv_path, append)?;
+        let mut new_writer = csv_writer(new_file);
+        if needs_header {
+            write_csv_header(&mut new_writer)?;
+            new_writer.flush()?;
+        }
+        let _ = mem::replace(&mut *self.file_handle.write().unwrap(), Some(new_writer));
+        *self.csv_path.write().unwrap() = csv_path;
         Ok(())
     }
 
-    /// Update the changes applied to the "Settings.toml" file.
+    /// The last time each keyword was searched.
+    pub fn last_run_from_state(&self) -> HashMap<String, NaiveDateTime> {
+        let reader = self.state.read().unwrap();
+        reader.last_run.clone()
+    }
+
+    /// Records the search of the keywords and saves the state, so the same
+    /// slot does not fire again after a restart.
+    pub fn record_run(
+        &self,
+        keyword: &HashSet<String>,
+        now: NaiveDateTime,
+    ) -> Result<(), Exception> {
+        let mut writer = self.state.write().unwrap();
+        for keyword in keyword {
+            writer.last_run.insert(keyword.to_string(), now);
+        }
+        writer.save()
+    }
+
+    /// The last time each keyword was searched and reported without an error.
+    pub fn last_success_from_state(&self) -> HashMap<String, NaiveDateTime> {
+        let reader = self.state.read().unwrap();
+        reader.last_success.clone()
+    }
+
+    /// Records the keywords of a run that was reported and saves the state.
+    pub fn record_success(
+        &self,
+        keyword: &HashSet<String>,
+        now: NaiveDateTime,
+    ) -> Result<(), Exception> {
+        let mut writer = self.state.write().unwrap();
+        for keyword in keyword {
+            writer.last_success.insert(keyword.to_string(), now);
+        }
+        writer.save()
+    }
+
+    /// Returns true when the paper was sent within "hours" before "now".
+    pub fn notified_within(&self, href: &str, now: NaiveDateTime, hours: u32) -> bool {
+        let reader = self.state.read().unwrap();
+        reader.notified_within(href, now, hours)
+    }
+
+    /// Records the papers sent at "now" and saves the state.
+    pub fn record_notified(
+        &self,
+        papers: &[Paper],
+        now: NaiveDateTime,
+        hours: u32,
+    ) -> Result<(), Exception> {
+        let mut writer = self.state.write().unwrap();
+        let keys = papers.iter().map(Paper::dedup_key).collect::<Vec<String>>();
+        writer.record_notified(keys.iter().map(String::as_str), now, hours);
+        writer.save()
+    }
+
+    /// Update the changes applied to the "Settings.toml" file. The file is
+    /// only read again when its modified time has changed, and a file that
+    /// fails to parse is read again until it is fixed.
     pub fn update_settings(&self) -> Result<(), Exception> {
+        let modified = settings_modified();
+        let mut settings_modified = self.settings_modified.write().unwrap();
+        if modified.is_some() && modified == *settings_modified {
+            return Ok(());
+        }
         let mut writer = self.settings.write().unwrap();
         writer.update_settings()?;
+        *settings_modified = modified;
+        tracing::info!("settings reloaded");
         Ok(())
     }
 
@@ -91,112 +344,1090 @@ impl Storage {
         reader.keyword.clone()
     }
 
-    pub fn time_from_settings(&self) -> (u32, u32, Weekday) {
+    /// The schedule of each keyword. Keywords without their own schedule
+    /// follow the global "weekday" and "time".
+    pub fn schedule_from_settings(&self) -> HashMap<String, Schedule> {
+        let reader = self.settings.read().unwrap();
+        let global = Schedule::Weekly {
+            weekdays: reader.weekdays.clone(),
+            times: reader.times.clone(),
+        };
+        reader
+            .keyword
+            .iter()
+            .map(|keyword| {
+                let schedule = reader.keyword_schedule.get(keyword).unwrap_or(&global);
+                (keyword.to_string(), schedule.clone())
+            })
+            .collect()
+    }
+
+    pub fn grace_from_settings(&self) -> u32 {
+        let reader = self.settings.read().unwrap();
+        reader.grace_minutes
+    }
+
+    pub fn catch_up_from_settings(&self) -> bool {
+        let reader = self.settings.read().unwrap();
+        reader.catch_up
+    }
+
+    /// The current time in "timezone", which the schedule follows.
+    pub fn local_now(&self) -> NaiveDateTime {
+        let reader = self.settings.read().unwrap();
+        now_in(reader.timezone)
+    }
+
+    pub fn suppress_repeat_from_settings(&self) -> Option<u32> {
+        let reader = self.settings.read().unwrap();
+        reader.suppress_repeat_hours
+    }
+
+    pub fn max_results_from_settings(&self) -> Option<usize> {
+        let reader = self.settings.read().unwrap();
+        reader.max_results_per_run
+    }
+
+    pub fn max_papers_from_settings(&self) -> Option<usize> {
+        let reader = self.settings.read().unwrap();
+        reader.max_papers_per_run
+    }
+
+    pub fn bootstrap_days_from_settings(&self) -> Option<u32> {
+        let reader = self.settings.read().unwrap();
+        reader.bootstrap_days
+    }
+
+    /// The earliest publication date to keep on "today", and whether the
+    /// papers without a date are dropped.
+    pub fn since_from_settings(&self, today: NaiveDate) -> (Option<NaiveDate>, bool) {
+        let reader = self.settings.read().unwrap();
+        let since_days = reader
+            .since_days
+            .map(|days| today - chrono::Duration::days(days as i64));
+        (since_days.max(reader.since_date), reader.drop_undated)
+    }
+
+    pub fn abstract_max_chars_from_settings(&self) -> Option<usize> {
+        let reader = self.settings.read().unwrap();
+        reader.abstract_max_chars
+    }
+
+    pub fn fetch_abstracts_from_settings(&self) -> bool {
+        let reader = self.settings.read().unwrap();
+        reader.fetch_abstracts
+    }
+
+    pub fn abstract_timeout_from_settings(&self) -> Duration {
+        let reader = self.settings.read().unwrap();
+        Duration::from_millis(reader.abstract_timeout_ms)
+    }
+
+    pub fn display_sort_from_settings(&self) -> DisplaySort {
+        let reader = self.settings.read().unwrap();
+        reader.display_sort
+    }
+
+    pub fn scoring_from_settings(&self) -> Scoring {
+        let reader = self.settings.read().unwrap();
+        reader.scoring
+    }
+
+    pub fn journal_placeholder_from_settings(&self) -> String {
+        let reader = self.settings.read().unwrap();
+        reader.journal_placeholder.clone()
+    }
+
+    /// Returns false when the journal is left out by "journal_allowlist" or
+    /// "journal_blocklist".
+    pub fn journal_allowed(&self, journal: &str) -> bool {
+        let reader = self.settings.read().unwrap();
+        journal_allowed(
+            journal,
+            &reader.journal_allowlist,
+            &reader.journal_blocklist,
+        )
+    }
+
+    pub fn control_from_settings(&self) -> (Option<SocketAddr>, Option<String>) {
+        let reader = self.settings.read().unwrap();
+        let address = reader
+            .control_port
+            .map(|port| SocketAddr::new(reader.control_bind, port));
+        (address, reader.control_token.clone())
+    }
+
+    /// The user agents to rotate through. Empty when none is configured.
+    pub fn user_agents_from_settings(&self) -> Vec<String> {
+        let reader = self.settings.read().unwrap();
+        if !reader.user_agents.is_empty() {
+            return reader.user_agents.clone();
+        }
+        reader.user_agent.iter().cloned().collect()
+    }
+
+    /// Whether the browser runs headless, and its window size.
+    pub fn browser_from_settings(&self) -> (bool, Option<(u32, u32)>) {
+        let reader = self.settings.read().unwrap();
+        (reader.headless, reader.window_size)
+    }
+
+    /// The "Accept-Language" of the requests, when it is set.
+    pub fn language_from_settings(&self) -> Option<String> {
+        let reader = self.settings.read().unwrap();
+        reader.language.clone()
+    }
+
+    /// The selector of the consent button in place of the one of the source.
+    pub fn consent_selector_from_settings(&self) -> Option<String> {
+        let reader = self.settings.read().unwrap();
+        reader.consent_selector.clone()
+    }
+
+    /// How often the tab is replaced between the runs, and the url it opens.
+    pub fn keep_alive_from_settings(&self) -> (Duration, Option<String>) {
+        let reader = self.settings.read().unwrap();
+        (
+            Duration::from_secs(reader.keep_alive_secs),
+            reader.keep_alive_url.clone(),
+        )
+    }
+
+    /// The proxy server of the browser, and the hosts that bypass it.
+    pub fn proxy_from_settings(&self) -> (Option<String>, Vec<String>) {
+        let reader = self.settings.read().unwrap();
+        (reader.proxy.clone(), reader.proxy_bypass.clone())
+    }
+
+    /// The number of results on a page, and the number of pages to read.
+    pub fn pages_from_settings(&self) -> (usize, usize) {
+        let reader = self.settings.read().unwrap();
+        (reader.results_per_page, reader.max_pages)
+    }
+
+    /// The number of results read for the keyword, when it has its own.
+    pub fn limit_from_settings(&self, keyword: &str) -> Option<usize> {
+        let reader = self.settings.read().unwrap();
+        reader.keyword_limit.get(keyword).copied()
+    }
+
+    /// Where the keyword is looked for, and the order of the results.
+    pub fn search_field_from_settings(&self) -> (SearchField, SortBy) {
+        let reader = self.settings.read().unwrap();
+        (reader.search_field, reader.sort_by)
+    }
+
+    pub fn base_url_from_settings(&self) -> String {
+        let reader = self.settings.read().unwrap();
+        reader.base_url.clone()
+    }
+
+    pub fn element_timeout_from_settings(&self) -> Duration {
+        let reader = self.settings.read().unwrap();
+        Duration::from_millis(reader.element_timeout_ms)
+    }
+
+    /// The number of scroll steps down a result page, and the pause after
+    /// each step.
+    pub fn scroll_from_settings(&self) -> (u32, Duration) {
+        let reader = self.settings.read().unwrap();
+        (
+            reader.scroll_steps,
+            Duration::from_millis(reader.scroll_pause_ms),
+        )
+    }
+
+    pub fn max_retries_from_settings(&self) -> u32 {
+        let reader = self.settings.read().unwrap();
+        reader.max_retries
+    }
+
+    pub fn dry_run_from_settings(&self) -> bool {
+        let reader = self.settings.read().unwrap();
+        reader.dry_run
+    }
+
+    pub fn write_run_metadata_from_settings(&self) -> bool {
+        let reader = self.settings.read().unwrap();
+        reader.write_run_metadata
+    }
+
+    pub fn respect_robots_from_settings(&self) -> bool {
         let reader = self.settings.read().unwrap();
-        (reader.hour, reader.minute, reader.weekday)
+        reader.respect_robots
+    }
+
+    pub fn crawl_delay_from_settings(&self) -> Duration {
+        let reader = self.settings.read().unwrap();
+        Duration::from_millis(reader.crawl_delay_ms)
     }
 
     pub fn write_to_file(&self, paper: Paper) -> Result<(), Exception> {
-        let mut writer = self.file_handle.write().unwrap();
-        writer.serialize(paper)?;
+        let (stamp, append) = self.csv_mode_from_settings();
+        self.ensure_file_handle(stamp, append)?;
+        let mut file_handle = self.file_handle.write().unwrap();
+        let writer = file_handle.as_mut().unwrap();
+        write_csv_paper(writer, &paper)?;
         writer.flush()?;
-        
-        let mut counter = self.counter.write().unwrap();
-        *counter += 1;
+
+        self.counter.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Flushes the csv file and waits for the emails still being sent. An
+    /// email that could not be sent after "smtp_retries" is an error.
+    pub fn flush(&self) -> Result<(), Exception> {
+        if let Some(writer) = self.file_handle.write().unwrap().as_mut() {
+            writer.flush()?;
+        }
+        let failed = mem::take(&mut *self.sending.write().unwrap())
+            .into_iter()
+            .filter_map(join_sending)
+            .collect::<Vec<String>>();
+        if !failed.is_empty() {
+            return Err(Box::new(EmailException(failed.join("\n\t"))));
+        }
+        Ok(())
+    }
+
+    /// The errors of the emails that have given up sending since the last
+    /// call. The emails still being sent are kept.
+    pub fn failed_sending(&self) -> Vec<String> {
+        let mut sending = self.sending.write().unwrap();
+        let (finished, pending) = mem::take(&mut *sending)
+            .into_iter()
+            .partition::<Vec<_>, _>(|handle| handle.is_finished());
+        *sending = pending;
+        finished.into_iter().filter_map(join_sending).collect()
+    }
+
+    /// Writes the papers of the run as a JSON array to "json_output_path",
+    /// if it is set.
+    pub fn write_json(&self, papers: &[Paper]) -> Result<(), Exception> {
+        let json_output_path = match &self.settings.read().unwrap().json_output_path {
+            Some(json_output_path) => PathBuf::from(json_output_path),
+            None => return Ok(()),
+        };
+        if let Some(parent) = json_output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(json_output_path, serde_json::to_vec_pretty(papers)?)?;
         Ok(())
     }
 
-    pub fn send_email(&self, local_time: &str) -> Result<(), Exception> {
-        let mut counter = self.counter.write().unwrap();
-        if *counter > 0 {
-            let writer = self.settings.write().unwrap();
-            writer.send_email(local_time)?;
-            *counter = 0;
+    pub fn send_email(
+        &self,
+        local_time: &str,
+        papers: &[Paper],
+        snapshot: bool,
+        truncated: usize,
+    ) -> Result<(), Exception> {
+        let (stamp, append) = self.csv_mode_from_settings();
+        let counter = self.counter.load(Ordering::SeqCst);
+        let writer = self.settings.write().unwrap();
+        if counter > 0 && writer.group_by_keyword {
+            // One email per keyword, with only the papers of that keyword.
+            let mut groups = BTreeMap::<&str, Vec<Paper>>::new();
+            for paper in papers {
+                groups
+                    .entry(paper.keyword.as_str())
+                    .or_default()
+                    .push(paper.clone());
+            }
+            for group in groups.values() {
+                let handle = writer.send_email(local_time, group, snapshot, None, truncated)?;
+                self.track_sending(handle);
+            }
+            self.counter.store(0, Ordering::SeqCst);
+        } else if counter > 0 {
+            let csv_path = self.csv_path.read().unwrap();
+            let handle =
+                writer.send_email(local_time, papers, snapshot, Some(&csv_path), truncated)?;
+            self.track_sending(handle);
+            self.counter.store(0, Ordering::SeqCst);
+        } else if writer.send_empty {
+            // The heartbeat email still attaches a csv file with the header.
+            self.ensure_file_handle(stamp, append)?;
+            let csv_path = self.csv_path.read().unwrap();
+            let handle =
+                writer.send_email(local_time, papers, snapshot, Some(&csv_path), truncated)?;
+            self.track_sending(handle);
+        } else {
+            tracing::info!("No new papers, skipping email.");
         }
         Ok(())
     }
+
+    /// Keeps the email being sent until its result is taken by
+    /// [Storage::flush] or [Storage::failed_sending].
+    fn track_sending(&self, handle: JoinHandle<Result<(), String>>) {
+        self.sending.write().unwrap().push(handle);
+    }
+
+    /// Reports a failed run by email with "error_notify", at most once in
+    /// "error_notify_hours" so a lasting outage is not sent on every run.
+    pub fn send_error_email(&self, error: &str, now: NaiveDateTime) -> Result<(), Exception> {
+        let reader = self.settings.read().unwrap();
+        if !reader.error_notify || reader.dry_run {
+            return Ok(());
+        }
+
+        let mut state = self.state.write().unwrap();
+        if let Some(error_notified) = state.error_notified {
+            if now - error_notified < chrono::Duration::hours(reader.error_notify_hours as i64) {
+                tracing::info!(
+                    "A failed run was reported at {}, skipping email.",
+                    error_notified
+                );
+                return Ok(());
+            }
+        }
+        reader.send_error_email(error, now)?;
+        state.error_notified = Some(now);
+        state.save()
+    }
 }
 
 #[derive(Clone, serde::Serialize)]
 pub struct Paper {
     pub keyword: String,
     pub title: String,
+    pub authors: Vec<String>,
     pub journal: String,
+    pub published: Option<String>,
     pub href: String,
+    pub doi: Option<String>,
+    pub abstract_text: Option<String>,
+    pub score: f32,
+    pub match_location: MatchLocation,
 }
 
-/// Pretty-print on the console for debugging.
-impl Debug for Paper {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "\n\ttitle: {}\n\thref: {}\n\tkeyword: {}\n\tjournal: {}\n\
-            ==================================================",
-            self.title, self.href, self.keyword, self.journal,
-        )
-    }
+/// Where the keyword of a paper was found.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchLocation {
+    /// In the title.
+    Title,
+    /// Only in the abstract.
+    Abstract,
+    /// In neither, so the paper matched on the text not shown.
+    Neither,
 }
 
-/// Setter for key-value pairs in "Settings.toml" files.
-/// id and password are no longer optional fields. They
-/// need to be filled out in order to use the program.
-pub struct Settings {
-    pub keyword: HashSet<String>,
-    pub email: String,
-    pub hour: u32,
-    pub minute: u32,
-    pub weekday: Weekday,
-    id: String,
-    mailer: Option<SmtpTransport>,
+impl MatchLocation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MatchLocation::Title => "title",
+            MatchLocation::Abstract => "abstract",
+            MatchLocation::Neither => "neither",
+        }
+    }
 }
 
-impl Settings {
-    pub fn new() -> Result<Self, Exception> {
-        let mut me = Self {
-            keyword: HashSet::<String>::new(),
-            email: String::new(),
-            hour: 8,
-            minute: 30,
-            weekday: Weekday::Sun,
-            id: "".into(),
-            mailer: None,
+impl Paper {
+    /// The csv columns in the order of [Paper::record].
+    pub const COLUMNS: [&'static str; 9] = [
+        "keyword",
+        "title",
+        "authors",
+        "journal",
+        "published",
+        "href",
+        "doi",
+        "abstract_text",
+        "match_location",
+    ];
+
+    /// The last day of the publication date, which is a day ("15 March
+    /// 2023"), a month ("March 2023") or a year ("2023"), sometimes after
+    /// "Available online".
+    pub fn published_date(&self) -> Option<NaiveDate> {
+        let published = self.published.as_deref()?;
+        let published = published
+            .trim()
+            .trim_start_matches("Available online")
+            .trim();
+        if let Ok(date) = NaiveDate::parse_from_str(published, "%d %B %Y") {
+            return Some(date);
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(&format!("1 {}", published), "%d %B %Y") {
+            let next_month = match date.month() {
+                12 => NaiveDate::from_ymd_opt(date.year() + 1, 1, 1)?,
+                month => NaiveDate::from_ymd_opt(date.year(), month + 1, 1)?,
+            };
+            return next_month.pred_opt();
+        }
+        let year = published.parse::<i32>().ok()?;
+        NaiveDate::from_ymd_opt(year, 12, 31)
+    }
+
+    /// Returns true when the paper was published on or after "since". An
+    /// undated paper is never, as it can not be told.
+    pub fn published_since(&self, since: NaiveDate) -> bool {
+        self.published_date().map_or(false, |date| date >= since)
+    }
+
+    /// The key the paper is told apart by across runs. The same article
+    /// may be served under more than one url, so its DOI is preferred.
+    pub fn dedup_key(&self) -> String {
+        match &self.doi {
+            Some(doi) => doi.to_lowercase(),
+            None => normalize_href(&self.href),
+        }
+    }
+
+    /// The csv fields of the paper.
+    pub fn record(&self) -> Vec<String> {
+        vec![
+            self.keyword.clone(),
+            self.title.clone(),
+            self.authors.join("; "),
+            self.journal.clone(),
+            self.published.clone().unwrap_or_default(),
+            self.href.clone(),
+            self.doi.clone().unwrap_or_default(),
+            self.abstract_text.clone().unwrap_or_default(),
+            self.match_location.as_str().to_string(),
+        ]
+    }
+
+    /// Looks for the keyword in the title, then in the abstract. A keyword
+    /// of "OR" alternatives is found when one of them is, and an
+    /// alternative when all of its words are, in any case.
+    pub fn locate_keyword(&self) -> MatchLocation {
+        let alias_list = keyword_aliases(&self.keyword);
+        let found = |text: &str| {
+            let text = text.to_lowercase();
+            alias_list
+                .iter()
+                .any(|alias| alias.iter().all(|word| text.contains(word.as_str())))
         };
-        me.update_settings()?;
-        Ok(me)
+        if found(&self.title) {
+            MatchLocation::Title
+        } else if self.abstract_text.as_deref().map_or(false, found) {
+            MatchLocation::Abstract
+        } else {
+            MatchLocation::Neither
+        }
     }
+}
 
-    /// Load configurations from the Settings.toml file located at
-    /// the program root directory.
-    pub fn load_config(&self) -> Result<Config, Exception> {
-        // The base path for configs ("./Settings.toml").
-        let mut settings_path = env::current_dir()?;
-        settings_path.push("Settings.toml");
-        let settings_path_str = settings_path.to_str().unwrap();
+/// The lowercase words of each "OR" alternative of the keyword, without
+/// the quotes of a phrase, as [or_group] writes them.
+fn keyword_aliases(keyword: &str) -> Vec<Vec<String>> {
+    keyword
+        .split(" OR ")
+        .map(|alias| {
+            alias
+                .split_whitespace()
+                .map(|word| word.trim_matches('"').to_lowercase())
+                .filter(|word| !word.is_empty())
+                .collect::<Vec<String>>()
+        })
+        .filter(|alias| !alias.is_empty())
+        .collect()
+}
 
-        // Build the config file.
-        let config = Config::builder()
-            .add_source(config::File::with_name(settings_path_str))
-            .add_source(config::Environment::with_prefix("APP"))
-            .build()?;
-        Ok(config)
+/// The integer "value" of "key" as the type of its field. A value that does
+/// not fit, such as a negative count, is reported with the key.
+fn setting_int<T: TryFrom<i64>>(key: &str, value: &Value) -> Result<T, Exception> {
+    let int = value.clone().into_int()?;
+    T::try_from(int).map_err(|_| {
+        let message = format!("'{}' is out of range: {}", key, int);
+        Box::new(SettingsException(message)) as Exception
+    })
+}
+
+/// The keywords added to and removed from "previous" in "current", sorted.
+pub fn keyword_changes(
+    previous: &HashSet<String>,
+    current: &HashSet<String>,
+) -> (Vec<String>, Vec<String>) {
+    let mut added = current
+        .difference(previous)
+        .cloned()
+        .collect::<Vec<String>>();
+    let mut removed = previous
+        .difference(current)
+        .cloned()
+        .collect::<Vec<String>>();
+    added.sort();
+    removed.sort();
+    (added, removed)
+}
+
+/// The keyword of a "keyword" entry, which is either a keyword or a list
+/// of keywords searched as one with "OR".
+fn keyword_term(value: &Value, index: usize) -> Result<String, Exception> {
+    match value.clone().into_array() {
+        Ok(alias_list) => {
+            let alias_list = alias_list
+                .iter()
+                .map(|alias| alias.to_string())
+                .collect::<Vec<String>>();
+            if alias_list.iter().all(|alias| alias.trim().is_empty()) {
+                let message = format!("keyword[{}] is an empty list.", index);
+                return Err(Box::new(SettingsException(message)));
+            }
+            Ok(or_group(&alias_list))
+        }
+        Err(_) => Ok(value.to_string()),
     }
+}
 
-    /// Apply changes in Settings.toml file to the scheduler
-    /// during the runtime.
-    pub fn update_settings(&mut self) -> Result<(), Exception> {
-        let config = self.load_config()?;
-        self.update_keyword(&config)?;
-        self.update_email(&config)?;
-        self.update_time(&config)?;
-        self.update_weekday(&config)?;
-        self.update_profile(&config)?;
-        Ok(())
+/// Joins the keywords with "OR", quoting the ones of more than one word so
+/// they are searched as phrases.
+pub fn or_group(alias_list: &[String]) -> String {
+    let alias_list = alias_list
+        .iter()
+        .map(|alias| {
+            alias
+                .split_ascii_whitespace()
+                .collect::<Vec<&str>>()
+                .join(" ")
+        })
+        .filter(|alias| !alias.is_empty())
+        .collect::<Vec<String>>();
+    if alias_list.len() == 1 {
+        return alias_list[0].clone();
     }
+    alias_list
+        .iter()
+        .map(|alias| match alias.contains(' ') {
+            true => format!("\"{}\"", alias),
+            false => alias.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join(" OR ")
+}
 
-    /// It is a list of strings.
-    /// ```
-    /// keyword = ["X", "Y", "Z"]
-    /// ```
+/// The current time in the time zone, or in the local time of the host
+/// without one.
+pub fn now_in(timezone: Option<Tz>) -> NaiveDateTime {
+    match timezone {
+        Some(timezone) => Utc::now().with_timezone(&timezone).naive_local(),
+        None => Local::now().naive_local(),
+    }
+}
+
+/// The modified time of Settings.toml, or None when it can not be read.
+fn settings_modified() -> Option<SystemTime> {
+    let settings_path = load_settings_path().ok()?;
+    fs::metadata(settings_path).ok()?.modified().ok()
+}
+
+/// Waits for an email being sent and returns its error, if it gave up.
+fn join_sending(handle: JoinHandle<Result<(), String>>) -> Option<String> {
+    match handle.join() {
+        Ok(result) => result.err(),
+        Err(_) => Some("The email thread panicked.".to_string()),
+    }
+}
+
+/// The mail domain served by the SMTP host, "gmail.com" for
+/// "smtp.gmail.com". The "smtp" and "mail" labels in front are dropped, and
+/// the hosts named after something else than their domain are listed.
+pub fn from_domain(smtp_host: &str) -> String {
+    let smtp_host = smtp_host.trim().trim_end_matches('.').to_lowercase();
+    match smtp_host.as_str() {
+        "smtp.office365.com" | "smtp-mail.outlook.com" => return "outlook.com".to_string(),
+        "smtp.googlemail.com" => return "gmail.com".to_string(),
+        _ => {}
+    }
+
+    let mut domain = smtp_host.as_str();
+    while let Some((label, rest)) = domain.split_once('.') {
+        let is_mail_label = label.starts_with("smtp") || label == "mail";
+        // Keep at least a name and a top level domain.
+        if !is_mail_label || !rest.contains('.') {
+            break;
+        }
+        domain = rest;
+    }
+    domain.to_string()
+}
+
+/// Matches the journal against the lowercase words of the lists. An
+/// allowlist, when set, decides alone.
+pub fn journal_allowed(journal: &str, allowlist: &[String], blocklist: &[String]) -> bool {
+    let journal = journal.to_lowercase();
+    if !allowlist.is_empty() {
+        return allowlist.iter().any(|word| journal.contains(word.as_str()));
+    }
+    !blocklist.iter().any(|word| journal.contains(word.as_str()))
+}
+
+/// The href without its query string and fragment, which only carry
+/// tracking parameters on ScienceDirect, and without a trailing slash.
+pub fn normalize_href(href: &str) -> String {
+    let end = href.find(|c| c == '?' || c == '#').unwrap_or(href.len());
+    href[..end].trim_end_matches('/').to_string()
+}
+
+/// Columns that are always quoted, so spreadsheets and citation managers
+/// keep ID-like values such as DOIs as text instead of reading numbers.
+const TEXT_COLUMNS: &[&str] = &["doi"];
+
+/// "Papers_YYYYMMDD_HHMM.csv" next to the csv file, named after the time
+/// of the run in the configured time zone.
+fn timestamped_csv_path(stamp: NaiveDateTime) -> Result<PathBuf, Exception> {
+    let csv_path = load_csv_path()?;
+    let file_name = format!(
+        "{}_{}.csv",
+        csv_path.file_stem().unwrap_or_default().to_string_lossy(),
+        stamp.format("%Y%m%d_%H%M")
+    );
+    Ok(csv_path.with_file_name(file_name))
+}
+
+/// Opens the csv file, truncated or to be appended to. Returns the file and
+/// whether it still needs the header, which an appended file only does when
+/// it is new or empty.
+fn open_csv(csv_path: &Path, append: bool) -> Result<(File, bool), Exception> {
+    if !append {
+        return Ok((File::create(csv_path)?, true));
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(csv_path)?;
+    let needs_header = file.metadata()?.len() == 0;
+    Ok((file, needs_header))
+}
+
+/// Creates the csv file with the header only when it is missing or empty,
+/// and leaves a file with papers in it as is.
+fn ensure_csv_header(csv_path: &Path) -> Result<(), Exception> {
+    let (file, needs_header) = open_csv(csv_path, true)?;
+    if needs_header {
+        let mut writer = csv_writer(file);
+        write_csv_header(&mut writer)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Builds a csv writer. The fields are quoted one by one in
+/// [write_csv_paper], so the writer itself never quotes.
+pub fn csv_writer<W: io::Write>(writer: W) -> Writer<W> {
+    WriterBuilder::new()
+        .quote_style(QuoteStyle::Never)
+        .from_writer(writer)
+}
+
+pub fn write_csv_header<W: io::Write>(writer: &mut Writer<W>) -> Result<(), Exception> {
+    writer.write_record(
+        Paper::COLUMNS
+            .iter()
+            .map(|column| quote_field(column, false)),
+    )?;
+    Ok(())
+}
+
+/// Writes a paper, quoting the fields in [TEXT_COLUMNS] regardless of their
+/// content and the other fields only when it is necessary.
+pub fn write_csv_paper<W: io::Write>(
+    writer: &mut Writer<W>,
+    paper: &Paper,
+) -> Result<(), Exception> {
+    let record = Paper::COLUMNS
+        .iter()
+        .zip(paper.record())
+        .map(|(column, field)| quote_field(&field, TEXT_COLUMNS.contains(column)));
+    writer.write_record(record)?;
+    Ok(())
+}
+
+/// Writes the papers with the header into a csv buffer.
+pub fn csv_buffer(papers: &[Paper]) -> Result<Vec<u8>, Exception> {
+    let mut writer = csv_writer(Vec::new());
+    write_csv_header(&mut writer)?;
+    for paper in papers {
+        write_csv_paper(&mut writer, paper)?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+/// Builds an xlsx workbook of the papers with a header row, as the csv
+/// file has.
+pub fn xlsx_buffer(papers: &[Paper]) -> Result<Vec<u8>, Exception> {
+    let mut workbook = Workbook::new();
+    let worksheet = workbook.add_worksheet();
+    for (col, column) in Paper::COLUMNS.iter().enumerate() {
+        worksheet.write_string(0, col as u16, *column)?;
+    }
+    for (row, paper) in papers.iter().enumerate() {
+        for (col, field) in paper.record().iter().enumerate() {
+            worksheet.write_string(row as u32 + 1, col as u16, field)?;
+        }
+    }
+    Ok(workbook.save_to_buffer()?)
+}
+
+/// Reads a setting that is either a single string or a list of strings.
+fn string_or_list(key: &str, value: &Value) -> Result<Vec<String>, Exception> {
+    let list = match value.clone().into_array() {
+        Ok(array) => array.iter().map(|value| value.to_string()).collect(),
+        Err(_) => vec![value.to_string()],
+    };
+    if list.is_empty() {
+        let message = format!("'{}' must not be an empty list.", key);
+        return Err(Box::new(SettingsException(message)));
+    }
+    Ok(list)
+}
+
+/// Writes the paper as a single line of JSON and flushes it, so a reader on
+/// the other end of a pipe sees it at once.
+pub fn write_json_line<W: io::Write>(writer: &mut W, paper: &Paper) -> Result<(), Exception> {
+    serde_json::to_writer(&mut *writer, paper)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn quote_field(field: &str, always: bool) -> String {
+    if always || field.contains(|c: char| matches!(c, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Sorts the papers for the email and the csv file. The sort is stable, so
+/// papers with the same key stay in the order they were found. "date" puts
+/// the newest first across the keywords, and the undated papers last.
+pub fn sort_papers(papers: &mut [Paper], display_sort: DisplaySort) {
+    match display_sort {
+        DisplaySort::Date => papers.sort_by(|a, b| b.published_date().cmp(&a.published_date())),
+        DisplaySort::Title => papers.sort_by(|a, b| a.title.cmp(&b.title)),
+        DisplaySort::Journal => papers.sort_by(|a, b| a.journal.cmp(&b.journal)),
+        DisplaySort::Keyword => papers.sort_by(|a, b| a.keyword.cmp(&b.keyword)),
+        DisplaySort::Score => papers.sort_by(|a, b| b.score.total_cmp(&a.score)),
+    }
+}
+
+/// Keeps the "max_results" newest of the papers found in a run, along with
+/// their rank, and returns how many were dropped. The undated papers are
+/// dropped first.
+pub fn cap_papers(papers: &mut Vec<(usize, Paper)>, max_results: usize) -> usize {
+    papers.sort_by(|(_, a), (_, b)| b.published_date().cmp(&a.published_date()));
+    let dropped = papers.len().saturating_sub(max_results);
+    papers.truncate(max_results);
+    dropped
+}
+
+/// The weights of [score_paper] from the "[scoring]" table.
+#[derive(Clone, Copy)]
+pub struct Scoring {
+    pub title_weight: f32,
+    pub recency_weight: f32,
+}
+
+impl Default for Scoring {
+    fn default() -> Self {
+        Self {
+            title_weight: 1.0,
+            recency_weight: 1.0,
+        }
+    }
+}
+
+/// Scores the relevance of a paper. Every occurrence of a keyword term in
+/// the title adds "title_weight", and "recency_weight" is scaled down by
+/// the rank of the paper in its result list, in the order of "sort_by".
+pub fn score_paper(paper: &Paper, rank: usize, scoring: &Scoring) -> f32 {
+    let title = paper.title.to_lowercase();
+    let term_list = keyword_aliases(&paper.keyword)
+        .into_iter()
+        .flatten()
+        .collect::<BTreeSet<String>>();
+    let hit = term_list
+        .iter()
+        .map(|term| title.matches(term.as_str()).count())
+        .sum::<usize>();
+    let recency = 1.0 / (1.0 + rank as f32);
+    scoring.title_weight * hit as f32 + scoring.recency_weight * recency
+}
+
+/// Pretty-print on the console for debugging.
+impl Debug for Paper {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "\n\ttitle: {}\n\tauthors: {}\n\thref: {}\n\tkeyword: {} (in {})\n\tjournal: {}\n\
+            \tpublished: {}\n\
+            ==================================================",
+            self.title,
+            self.authors.join("; "),
+            self.href,
+            self.keyword,
+            self.match_location.as_str(),
+            self.journal,
+            self.published.as_deref().unwrap_or("-"),
+        )
+    }
+}
+
+/// Setter for key-value pairs in "Settings.toml" files.
+/// id and password are no longer optional fields. They
+/// need to be filled out in order to use the program.
+pub struct Settings {
+    pub keyword: HashSet<String>,
+    pub keyword_schedule: HashMap<String, Schedule>,
+    pub keyword_limit: HashMap<String, usize>,
+    pub email: String,
+    pub times: Vec<(u32, u32)>,
+    pub weekdays: HashSet<Weekday>,
+    pub grace_minutes: u32,
+    pub catch_up: bool,
+    pub timezone: Option<Tz>,
+    pub suppress_repeat_hours: Option<u32>,
+    pub max_results_per_run: Option<usize>,
+    pub max_papers_per_run: Option<usize>,
+    pub max_body_entries: Option<usize>,
+    pub template: Template,
+    pub bootstrap_days: Option<u32>,
+    pub since_days: Option<u32>,
+    pub since_date: Option<NaiveDate>,
+    pub drop_undated: bool,
+    pub fetch_abstracts: bool,
+    pub abstract_timeout_ms: u64,
+    pub abstract_max_chars: Option<usize>,
+    pub crawl_delay_ms: u64,
+    pub max_retries: u32,
+    pub element_timeout_ms: u64,
+    pub scroll_steps: u32,
+    pub scroll_pause_ms: u64,
+    pub results_per_page: usize,
+    pub max_pages: usize,
+    pub base_url: String,
+    pub search_field: SearchField,
+    pub sort_by: SortBy,
+    pub respect_robots: bool,
+    pub display_sort: DisplaySort,
+    pub scoring: Scoring,
+    pub journal_placeholder: String,
+    pub journal_allowlist: Vec<String>,
+    pub journal_blocklist: Vec<String>,
+    pub control_port: Option<u16>,
+    pub control_bind: IpAddr,
+    control_token: Option<String>,
+    pub smtp_reuse_connection: bool,
+    pub smtp_retries: u32,
+    pub smtp_timeout: Duration,
+    pub attachment_format: AttachmentFormat,
+    pub body_format: BodyFormat,
+    pub storage_backend: StorageBackend,
+    pub group_by_keyword: bool,
+    pub write_run_metadata: bool,
+    pub dry_run: bool,
+    pub send_empty: bool,
+    pub error_notify: bool,
+    pub error_notify_hours: u32,
+    pub json_output_path: Option<String>,
+    pub csv_mode: CsvMode,
+    pub timestamped_output: bool,
+    pub headless: bool,
+    pub window_size: Option<(u32, u32)>,
+    pub user_agent: Option<String>,
+    pub user_agents: Vec<String>,
+    pub proxy: Option<String>,
+    pub proxy_bypass: Vec<String>,
+    pub keep_alive_secs: u64,
+    pub keep_alive_url: Option<String>,
+    pub consent_selector: Option<String>,
+    pub language: Option<String>,
+    pub smtp_host: String,
+    pub smtp_port: Option<u16>,
+    pub smtp_encryption: SmtpEncryption,
+    pub from_address: Option<String>,
+    pub from_name: Option<String>,
+    pub from_email: Option<String>,
+    id: String,
+    password: String,
+    mailer: Option<SmtpTransport>,
+}
+
+impl Settings {
+    pub fn new() -> Result<Self, Exception> {
+        let mut me = Self::defaults();
+        me.update_settings()?;
+        Ok(me)
+    }
+
+    /// The settings before Settings.toml is read.
+    fn defaults() -> Self {
+        Self {
+            keyword: HashSet::<String>::new(),
+            keyword_schedule: HashMap::<String, Schedule>::new(),
+            keyword_limit: HashMap::<String, usize>::new(),
+            email: String::new(),
+            times: vec![(8, 30)],
+            weekdays: HashSet::from([Weekday::Sun]),
+            grace_minutes: 2,
+            catch_up: false,
+            timezone: None,
+            suppress_repeat_hours: None,
+            max_results_per_run: None,
+            max_papers_per_run: None,
+            max_body_entries: None,
+            template: Template::default(),
+            bootstrap_days: None,
+            since_days: None,
+            since_date: None,
+            drop_undated: false,
+            fetch_abstracts: false,
+            abstract_timeout_ms: 10000,
+            abstract_max_chars: None,
+            crawl_delay_ms: 1000,
+            max_retries: 3,
+            element_timeout_ms: 10000,
+            scroll_steps: 0,
+            scroll_pause_ms: 300,
+            results_per_page: 50,
+            max_pages: 1,
+            base_url: "https://www.sciencedirect.com/".into(),
+            search_field: SearchField::All,
+            sort_by: SortBy::Date,
+            respect_robots: false,
+            display_sort: DisplaySort::Date,
+            scoring: Scoring::default(),
+            journal_placeholder: String::new(),
+            journal_allowlist: Vec::new(),
+            journal_blocklist: Vec::new(),
+            control_port: None,
+            control_bind: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            control_token: None,
+            smtp_reuse_connection: false,
+            smtp_retries: 3,
+            smtp_timeout: Duration::from_secs(60),
+            attachment_format: AttachmentFormat::Csv,
+            body_format: BodyFormat::Html,
+            storage_backend: StorageBackend::Memory,
+            group_by_keyword: false,
+            write_run_metadata: false,
+            dry_run: false,
+            send_empty: false,
+            error_notify: false,
+            error_notify_hours: 24,
+            json_output_path: None,
+            csv_mode: CsvMode::Overwrite,
+            timestamped_output: false,
+            headless: true,
+            window_size: None,
+            user_agent: None,
+            user_agents: Vec::new(),
+            proxy: None,
+            proxy_bypass: Vec::new(),
+            keep_alive_secs: 60,
+            keep_alive_url: None,
+            consent_selector: None,
+            language: None,
+            smtp_host: "smtp.naver.com".into(),
+            smtp_port: None,
+            smtp_encryption: SmtpEncryption::Tls,
+            from_address: None,
+            from_name: None,
+            from_email: None,
+            id: "".into(),
+            password: "".into(),
+            mailer: None,
+        }
+    }
+
+    /// Load configurations from the Settings.toml file located at
+    /// the program root directory. A missing file is replaced with the
+    /// commented template to fill in.
+    pub fn load_config() -> Result<Config, Exception> {
+        // The base path for configs ("./Settings.toml").
+        let settings_path = load_settings_path()?;
+        if !settings_path.exists() {
+            fs::write(&settings_path, SETTINGS_TEMPLATE)?;
+            let message = format!(
+                "{} was not found, so a template was written there. \
+                Fill in the keyword, the email and [profile], then run again.",
+                settings_path.display()
+            );
+            return Err(Box::new(SettingsException(message)));
+        }
+        let settings_path_str = settings_path.to_str().unwrap();
+
+        // Build the config file.
+        let config = Config::builder()
+            .add_source(config::File::with_name(settings_path_str))
+            .add_source(config::Environment::with_prefix("APP"))
+            .build();
+        match config {
+            Ok(config) => Ok(config),
+            Err(e) => {
+                let message = format!("{} is malformed: {}", settings_path.display(), e);
+                Err(Box::new(SettingsException(message)))
+            }
+        }
+    }
+
+    /// Reports the first key that Settings.toml can not do without and is
+    /// missing, instead of failing on it somewhere in the middle.
+    fn check_required(config: &Config) -> Result<(), Exception> {
+        for (table, key) in REQUIRED_KEYS {
+            if config.get::<Value>(&format!("{}.{}", table, key)).is_err() {
+                let message = format!("'{}' is missing from [{}] in Settings.toml.", key, table);
+                return Err(Box::new(SettingsException(message)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply changes in Settings.toml file to the scheduler
+    /// during the runtime.
+    pub fn update_settings(&mut self) -> Result<(), Exception> {
+        let config = Self::load_config()?;
+        Self::check_required(&config)?;
+        self.update_email(&config)?;
+        self.update_time(&config)?;
+        self.update_weekday(&config)?;
+        self.update_grace(&config)?;
+        self.update_catch_up(&config)?;
+        self.update_timezone(&config)?;
+        self.update_keyword(&config)?;
+        self.update_max_results(&config)?;
+        self.update_suppress_repeat(&config)?;
+        self.update_max_body_entries(&config)?;
+        self.update_template(&config)?;
+        self.update_bootstrap_days(&config)?;
+        self.update_since(&config)?;
+        self.update_abstract(&config)?;
+        self.update_crawl_delay(&config)?;
+        self.update_max_retries(&config)?;
+        self.update_scroll(&config)?;
+        self.update_pages(&config)?;
+        self.update_search_field(&config)?;
+        self.update_base_url(&config)?;
+        self.update_display_sort(&config)?;
+        self.update_journal_placeholder(&config)?;
+        self.update_journal_filter(&config)?;
+        self.update_control(&config)?;
+        self.update_smtp(&config)?;
+        self.update_attachment_format(&config)?;
+        self.update_body_format(&config)?;
+        self.update_group_by_keyword(&config)?;
+        self.update_storage_backend(&config)?;
+        self.update_run_metadata(&config)?;
+        self.update_dry_run(&config)?;
+        self.update_send_empty(&config)?;
+        self.update_error_notify(&config)?;
+        self.update_json_output(&config)?;
+        self.update_csv_mode(&config)?;
+        self.update_browser(&config)?;
+        self.update_profile(&config)?;
+        Ok(())
+    }
+
+    /// It is a list of strings.
+    /// ```
+    /// keyword = ["X", "Y", "Z"]
+    /// ```
     /// The below format is also allowed in TOML.
     /// ```
     /// keyword = [
@@ -205,106 +1436,1098 @@ impl Settings {
     ///     "Z",
     /// ]
     /// ```
+    /// A keyword can also be an object with its own schedule that overrides
+    /// the global "weekday" and "time" for that keyword only. Either set
+    /// "weekday" and/or "time", or search every "interval_hours" hours.
+    /// ```
+    /// keyword = [
+    ///     "X",
+    ///     { term = "Y", weekday = "Mon", time = "09:00" },
+    ///     { term = "Z", interval_hours = 24 },
+    /// ]
+    /// ```
+    ///
+    /// A list of keywords in place of a keyword is searched as one topic,
+    /// joined by "OR" into a single query. Its papers are labeled and told
+    /// apart by the joined keyword, such as 'graphene OR graphite'.
+    /// ```
+    /// keyword = [
+    ///     ["graphene", "graphite"],
+    ///     { term = ["lithium ion", "sodium ion"], weekday = "Mon" },
+    /// ]
+    /// ```
+    ///
+    /// A keyword object can also set "limit", the number of results read for
+    /// that keyword in place of "results_per_page" and "max_pages".
+    /// ```
+    /// keyword = [{ term = "battery", limit = 100 }, "graphene"]
+    /// ```
+    ///
+    /// A keyword listed twice with the same definition is collapsed into one,
+    /// but listing it with different schedules or limits is an error.
     fn update_keyword(&mut self, config: &Config) -> Result<(), Exception> {
         let table = config.get_table("default")?;
-        let keyword: HashSet<String> = table
-            .get("keyword")
-            .unwrap()
-            .clone()
-            .into_array()?
-            .iter()
-            .map(|x| x.to_string())
-            .collect();
-        self.keyword = keyword;
+        let mut definition = HashMap::<String, (Option<Schedule>, Option<usize>)>::new();
+        let mut duplicate = Vec::<String>::new();
+        let keyword_list = table.get("keyword").unwrap().clone().into_array()?;
+        for (index, value) in keyword_list.into_iter().enumerate() {
+            let (term, schedule, limit) = match value.clone().into_table() {
+                Ok(object) => {
+                    let term = match object.get("term") {
+                        Some(term) => keyword_term(term, index)?,
+                        None => {
+                            let message = "A keyword object is missing 'term'.".to_string();
+                            return Err(Box::new(SettingsException(message)));
+                        }
+                    };
+                    let limit = match object.get("limit") {
+                        Some(limit) => Some(setting_int::<usize>("limit", limit)?),
+                        None => None,
+                    };
+                    if limit == Some(0) {
+                        let message = format!("keyword '{}' has limit = 0.", term);
+                        return Err(Box::new(SettingsException(message)));
+                    }
+                    (term, self.parse_schedule(&object)?, limit)
+                }
+                Err(_) => (keyword_term(&value, index)?, None, None),
+            };
+
+            // An empty keyword would search for everything.
+            if term.trim().is_empty() {
+                let message = format!("keyword[{}] is empty.", index);
+                return Err(Box::new(SettingsException(message)));
+            }
+
+            // Conflicting definitions of the same keyword.
+            if let Some(previous) = definition.get(&term) {
+                if *previous != (schedule.clone(), limit) {
+                    let message = format!(
+                        "keyword '{}' is defined more than once with different schedules or limits.",
+                        term
+                    );
+                    return Err(Box::new(SettingsException(message)));
+                }
+                duplicate.push(term.to_string());
+            }
+            definition.insert(term, (schedule, limit));
+        }
+        if !duplicate.is_empty() {
+            tracing::warn!("Duplicate keywords are searched once: {:?}", duplicate);
+        }
+
+        self.keyword = definition.keys().cloned().collect();
+        self.keyword_limit = definition
+            .iter()
+            .filter_map(|(term, (_, limit))| limit.map(|limit| (term.to_string(), limit)))
+            .collect();
+        self.keyword_schedule = definition
+            .into_iter()
+            .filter_map(|(term, (schedule, _))| schedule.map(|schedule| (term, schedule)))
+            .collect();
+        Ok(())
+    }
+
+    /// Reads the schedule of a keyword object. Returns None when the object
+    /// follows the global schedule.
+    fn parse_schedule(
+        &mut self,
+        object: &Map<String, Value>,
+    ) -> Result<Option<Schedule>, Exception> {
+        if let Some(interval_hours) = object.get("interval_hours") {
+            let hours = setting_int::<u32>("interval_hours", interval_hours)?;
+            if hours == 0 {
+                let message = "interval_hours must be greater than 0.".to_string();
+                return Err(Box::new(SettingsException(message)));
+            }
+            return Ok(Some(Schedule::Interval { hours }));
+        }
+
+        if object.get("weekday").is_none() && object.get("time").is_none() {
+            return Ok(None);
+        }
+        let weekdays = match object.get("weekday") {
+            Some(value) => Self::parse_weekdays(value)?,
+            None => self.weekdays.clone(),
+        };
+        let times = match object.get("time") {
+            Some(value) => self.parse_alarm_times(value)?,
+            None => self.times.clone(),
+        };
+        Ok(Some(Schedule::Weekly { weekdays, times }))
+    }
+
+    /// The regular email address string.
+    /// ```
+    /// email = "zombiedelah@gmail.com"
+    /// ```
+    fn update_email(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        let email: String = table.get("email").unwrap().to_string();
+
+        // A typo is reported now rather than when the email is sent.
+        if let Err(e) = email.parse::<Mailbox>() {
+            let message = format!("email = '{}' is invalid: {}", email, e);
+            return Err(Box::new(SettingsException(message)));
+        }
+        self.email = email;
+        Ok(())
+    }
+
+    /// The hour and the minute to receive the email on. A list sends an
+    /// email at each of the times.
+    ///
+    /// 0 <= "HH" < 24
+    ///
+    /// 0 <= "MM" < 60
+    /// ```
+    /// time = "HH:MM"
+    /// time = ["09:00", "18:00"]
+    /// ```
+    fn update_time(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.times = self.parse_alarm_times(table.get("time").unwrap())?;
+        Ok(())
+    }
+
+    fn parse_alarm_times(&mut self, value: &Value) -> Result<Vec<(u32, u32)>, Exception> {
+        let mut times = Vec::new();
+        for alarm_time in string_or_list("time", value)? {
+            times.push(Self::parse_alarm_time(alarm_time)?);
+        }
+        Ok(times)
+    }
+
+    fn parse_alarm_time(alarm_time: String) -> Result<(u32, u32), Exception> {
+        // Missing splicer ':'.
+        if !alarm_time.contains(':') {
+            let message = "Missing splicer ':' in the time format.".to_string();
+            return Err(Box::new(TimeFormatException((message, alarm_time))));
+        }
+
+        // Wrong format or range.
+        let (hh, mm) = alarm_time.split_once(':').unwrap();
+        let hour = Self::parse_time(hh, UnitTime::Hour)?;
+        let minute = Self::parse_time(mm, UnitTime::Minute)?;
+        Ok((hour, minute))
+    }
+
+    fn parse_time(time_str: &str, ut: UnitTime) -> Result<u32, Exception> {
+        match ut {
+            UnitTime::Hour => {
+                let hour = match time_str.parse::<u32>() {
+                    Ok(hour) => hour,
+                    Err(_) => {
+                        let message = "Set hour as a number 'HH'".to_string();
+                        return Err(Box::new(TimeFormatException((message, time_str.into()))));
+                    }
+                };
+                if hour >= 24 {
+                    let message = "Set hour between 0 <= 'HH' < 24".to_string();
+                    return Err(Box::new(TimeFormatException((message, hour.to_string()))));
+                }
+
+                Ok(hour)
+            }
+            UnitTime::Minute => {
+                let minute = match time_str.parse::<u32>() {
+                    Ok(minute) => minute,
+                    Err(_) => {
+                        let message = "Set minute as a number 'MM'".to_string();
+                        return Err(Box::new(TimeFormatException((message, time_str.into()))));
+                    }
+                };
+                if minute >= 60 {
+                    let message = "Set minute between 0 <= 'MM' < 60".to_string();
+                    return Err(Box::new(TimeFormatException((message, minute.to_string()))));
+                }
+
+                Ok(minute)
+            }
+        }
+    }
+
+    /// Choose one or more of the weekdays to receive an email on, or one of
+    /// the shortcuts "daily", "weekday" (Mon to Fri) and "weekend".
+    /// ```
+    /// weekday = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"]
+    /// weekday = ["daily", "weekday", "weekend"]
+    /// ```
+    fn update_weekday(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.weekdays = Self::parse_weekdays(table.get("weekday").unwrap())?;
+        Ok(())
+    }
+
+    fn parse_weekdays(value: &Value) -> Result<HashSet<Weekday>, Exception> {
+        let mut weekdays = HashSet::new();
+        for weekday_value in string_or_list("weekday", value)? {
+            weekdays.extend(Self::parse_weekday(weekday_value)?);
+        }
+        Ok(weekdays)
+    }
+
+    fn parse_weekday(weekday_value: String) -> Result<Vec<Weekday>, Exception> {
+        use Weekday::*;
+        let weekday = match weekday_value.as_str() {
+            "Mon" => Ok(vec![Mon]),
+            "Tue" => Ok(vec![Tue]),
+            "Wed" => Ok(vec![Wed]),
+            "Thu" => Ok(vec![Thu]),
+            "Fri" => Ok(vec![Fri]),
+            "Sat" => Ok(vec![Sat]),
+            "Sun" => Ok(vec![Sun]),
+            "daily" => Ok(vec![Mon, Tue, Wed, Thu, Fri, Sat, Sun]),
+            "weekday" => Ok(vec![Mon, Tue, Wed, Thu, Fri]),
+            "weekend" => Ok(vec![Sat, Sun]),
+            _ => Err(Box::new(WeekdayException(weekday_value))),
+        }?;
+        Ok(weekday)
+    }
+
+    /// The minutes after the scheduled time in which a missed slot still
+    /// fires. It keeps a slow loop from stepping over the scheduled minute.
+    /// ```
+    /// grace_minutes = 2
+    /// ```
+    fn update_grace(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.grace_minutes = match table.get("grace_minutes") {
+            Some(value) => setting_int::<u32>("grace_minutes", value)?,
+            None => 2,
+        };
+        Ok(())
+    }
+
+    /// Search on startup the keywords whose slot of today has passed while
+    /// the crawler was down, instead of waiting for their next slot. Off by
+    /// default.
+    /// ```
+    /// catch_up = true
+    /// ```
+    fn update_catch_up(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.catch_up = match table.get("catch_up") {
+            Some(value) => value.clone().into_bool()?,
+            None => false,
+        };
+        Ok(())
+    }
+
+    /// The IANA time zone the schedule is kept in, instead of the local
+    /// time of the host. Useful on a server that runs in UTC.
+    /// ```
+    /// timezone = "Asia/Seoul"
+    /// ```
+    fn update_timezone(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.timezone = match table.get("timezone") {
+            Some(value) => {
+                let timezone = value.to_string();
+                match timezone.parse::<Tz>() {
+                    Ok(timezone) => Some(timezone),
+                    Err(_) => {
+                        let message = format!(
+                            "timezone = '{}' is not an IANA time zone such as 'Asia/Seoul'.",
+                            timezone
+                        );
+                        return Err(Box::new(SettingsException(message)));
+                    }
+                }
+            }
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// The maximum number of papers reported in a single run. The cap is
+    /// applied after the papers of every keyword are collected, keeping
+    /// the newest ones. The dropped papers are already recorded as seen, so
+    /// they are never sent; only the log counts them. Leave it out to
+    /// report every new paper.
+    ///
+    /// "max_papers_per_run" stops taking new papers once the run has found
+    /// that many instead. The rest are left unseen for the next run, and the
+    /// email says that the results were truncated.
+    /// ```
+    /// max_results_per_run = 30
+    /// max_papers_per_run = 200
+    /// ```
+    fn update_max_results(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.max_results_per_run = match table.get("max_results_per_run") {
+            Some(value) => Some(setting_int::<usize>("max_results_per_run", value)?),
+            None => None,
+        };
+        self.max_papers_per_run = match table.get("max_papers_per_run") {
+            Some(value) => Some(setting_int::<usize>("max_papers_per_run", value)?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Papers sent within the last N hours are not sent again, even when
+    /// they come back as new. Leave it out to send every new paper.
+    /// ```
+    /// suppress_repeat_hours = 168
+    /// ```
+    fn update_suppress_repeat(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.suppress_repeat_hours = match table.get("suppress_repeat_hours") {
+            Some(value) => Some(setting_int::<u32>("suppress_repeat_hours", value)?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// The maximum number of papers listed in the email body. The rest are
+    /// noted at the end, and the attachment still has all of them.
+    /// ```
+    /// max_body_entries = 20
+    /// ```
+    fn update_max_body_entries(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.max_body_entries = match table.get("max_body_entries") {
+            Some(value) => Some(setting_int::<usize>("max_body_entries", value)?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// The template file for the subject and the body of the email. The
+    /// built-in English template is used when it is not set or the file
+    /// is missing. See [Template] for the placeholders. "subject_template"
+    /// replaces the subject of the template.
+    /// ```
+    /// template_path = "Template.toml"
+    /// subject_template = "LinkDrive: {count} new papers on {date} ({keywords})"
+    /// ```
+    fn update_template(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.template = match table.get("template_path") {
+            Some(value) => Template::load(&value.to_string())?,
+            None => Template::default(),
+        };
+        if let Some(value) = table.get("subject_template") {
+            self.template.set_subject(value.to_string());
+        }
+        Ok(())
+    }
+
+    /// The first search of a new keyword only records the papers it finds
+    /// without sending them. This limits that search to the last N days,
+    /// rounded out to whole years by ScienceDirect.
+    /// ```
+    /// bootstrap_days = 30
+    /// ```
+    fn update_bootstrap_days(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.bootstrap_days = match table.get("bootstrap_days") {
+            Some(value) => Some(setting_int::<u32>("bootstrap_days", value)?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Drop the papers published before the last "since_days" days or before
+    /// "since_date", whichever is later. Papers without a readable date are
+    /// kept unless "drop_undated" is set.
+    /// ```
+    /// since_days = 365
+    /// since_date = "2023-01-01"
+    /// drop_undated = false
+    /// ```
+    fn update_since(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.since_days = match table.get("since_days") {
+            Some(value) => Some(setting_int::<u32>("since_days", value)?),
+            None => None,
+        };
+        self.since_date = match table.get("since_date") {
+            Some(value) => {
+                let since_date = value.to_string();
+                match NaiveDate::parse_from_str(&since_date, "%Y-%m-%d") {
+                    Ok(since_date) => Some(since_date),
+                    Err(_) => {
+                        let message =
+                            format!("since_date = '{}' is not a YYYY-MM-DD date.", since_date);
+                        return Err(Box::new(SettingsException(message)));
+                    }
+                }
+            }
+            None => None,
+        };
+        self.drop_undated = match table.get("drop_undated") {
+            Some(value) => value.clone().into_bool()?,
+            None => false,
+        };
+        Ok(())
+    }
+
+    /// Visit the page of each new paper to fetch its abstract. It loads
+    /// one more page per paper, so it is turned off by default. Otherwise
+    /// the preview on the result page is used, when there is one.
+    ///
+    /// Abstracts longer than "abstract_max_chars" characters are cut.
+    /// ```
+    /// fetch_abstracts = true
+    /// abstract_timeout_ms = 10000
+    /// abstract_max_chars = 300
+    /// ```
+    fn update_abstract(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.fetch_abstracts = match table.get("fetch_abstracts") {
+            Some(value) => value.clone().into_bool()?,
+            None => false,
+        };
+        self.abstract_timeout_ms = match table.get("abstract_timeout_ms") {
+            Some(value) => setting_int::<u64>("abstract_timeout_ms", value)?,
+            None => 10000,
+        };
+        self.abstract_max_chars = match table.get("abstract_max_chars") {
+            Some(value) => Some(setting_int::<usize>("abstract_max_chars", value)?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// The delay in milliseconds between page loads and between keywords.
+    /// With "respect_robots", the crawl-delay in the robots.txt of the site
+    /// is read at startup and the longer of the two is used.
+    /// ```
+    /// crawl_delay_ms = 1000
+    /// respect_robots = true
+    /// ```
+    fn update_crawl_delay(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.crawl_delay_ms = match table.get("crawl_delay_ms") {
+            Some(value) => setting_int::<u64>("crawl_delay_ms", value)?,
+            None => 1000,
+        };
+        self.respect_robots = match table.get("respect_robots") {
+            Some(value) => value.clone().into_bool()?,
+            None => false,
+        };
+        Ok(())
+    }
+
+    /// The number of times a timed out result page is loaded again before
+    /// the keyword is given up for the run. The wait doubles from 2 seconds
+    /// on each retry. "element_timeout_ms" is how long to wait for the
+    /// results on a page.
+    /// ```
+    /// max_retries = 3
+    /// element_timeout_ms = 10000
+    /// ```
+    fn update_max_retries(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.max_retries = match table.get("max_retries") {
+            Some(value) => setting_int::<u32>("max_retries", value)?,
+            None => 3,
+        };
+        self.element_timeout_ms = match table.get("element_timeout_ms") {
+            Some(value) => setting_int::<u64>("element_timeout_ms", value)?,
+            None => 10000,
+        };
+        Ok(())
+    }
+
+    /// Scroll down the result page in "scroll_steps" steps, pausing
+    /// "scroll_pause_ms" after each, so the lazily rendered fields of the
+    /// later results are filled in before they are read. 0 turns it off.
+    /// ```
+    /// scroll_steps = 5
+    /// scroll_pause_ms = 300
+    /// ```
+    fn update_scroll(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.scroll_steps = match table.get("scroll_steps") {
+            Some(value) => setting_int::<u32>("scroll_steps", value)?,
+            None => 0,
+        };
+        self.scroll_pause_ms = match table.get("scroll_pause_ms") {
+            Some(value) => setting_int::<u64>("scroll_pause_ms", value)?,
+            None => 300,
+        };
+        Ok(())
+    }
+
+    /// The number of results on a result page, one of 25, 50 and 100, and
+    /// the number of pages to read for each keyword. The next page is only
+    /// read when the previous one is full. "results_per_page" is read once
+    /// at startup.
+    /// ```
+    /// results_per_page = 50
+    /// max_pages = 3
+    /// ```
+    fn update_pages(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.results_per_page = match table.get("results_per_page") {
+            Some(value) => setting_int::<usize>("results_per_page", value)?,
+            None => 50,
+        };
+        if ![25, 50, 100].contains(&self.results_per_page) {
+            let message = format!(
+                "results_per_page = {} is not one of 25, 50, 100.",
+                self.results_per_page
+            );
+            return Err(Box::new(SettingsException(message)));
+        }
+        self.max_pages = match table.get("max_pages") {
+            Some(value) => setting_int::<usize>("max_pages", value)?,
+            None => 1,
+        };
+        if self.max_pages == 0 {
+            let message = "max_pages must be greater than 0.".to_string();
+            return Err(Box::new(SettingsException(message)));
+        }
+        Ok(())
+    }
+
+    /// The root url of the site that is searched, for a mirror or a local
+    /// copy of ScienceDirect in a test. Read once at startup.
+    /// ```
+    /// base_url = "https://www.sciencedirect.com/"
+    /// ```
+    fn update_base_url(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.base_url = match table.get("base_url") {
+            Some(value) => value.to_string(),
+            None => "https://www.sciencedirect.com/".to_string(),
+        };
+        Ok(())
+    }
+
+    /// Where the keyword is looked for. "all" searches the full text, and
+    /// "title" only the titles, which cuts the noise of a precise keyword.
+    /// "sort_by" orders the search results, newest first by default or by
+    /// relevance for an exploratory keyword. Both are read once at startup.
+    /// ```
+    /// search_field = ["all", "title"]
+    /// sort_by = ["date", "relevance"]
+    /// ```
+    fn update_search_field(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        let search_field = match table.get("search_field") {
+            Some(value) => value.to_string(),
+            None => "all".to_string(),
+        };
+
+        self.search_field = match search_field.as_str() {
+            "all" => Ok(SearchField::All),
+            "title" => Ok(SearchField::Title),
+            _ => {
+                let message = format!(
+                    "search_field = '{}' is not one of 'all', 'title'.",
+                    search_field
+                );
+                Err(Box::new(SettingsException(message)))
+            }
+        }?;
+
+        let sort_by = match table.get("sort_by") {
+            Some(value) => value.to_string(),
+            None => "date".to_string(),
+        };
+        self.sort_by = match sort_by.as_str() {
+            "date" => Ok(SortBy::Date),
+            "relevance" => Ok(SortBy::Relevance),
+            _ => {
+                let message = format!("sort_by = '{}' is not one of 'date', 'relevance'.", sort_by);
+                Err(Box::new(SettingsException(message)))
+            }
+        }?;
+        Ok(())
+    }
+
+    /// The order of papers in the email and the csv file. It is separate
+    /// from the order of the search query, which "sort_by" sets. "score" puts
+    /// the most relevant papers first, weighted by the "[scoring]" table.
+    /// ```
+    /// display_sort = ["date", "title", "journal", "keyword", "score"]
+    ///
+    /// [scoring]
+    /// title_weight = 1.0
+    /// recency_weight = 1.0
+    /// ```
+    fn update_display_sort(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        let display_sort = match table.get("display_sort") {
+            Some(value) => value.to_string(),
+            None => "date".to_string(),
+        };
+
+        self.display_sort = match display_sort.as_str() {
+            "date" => Ok(DisplaySort::Date),
+            "title" => Ok(DisplaySort::Title),
+            "journal" => Ok(DisplaySort::Journal),
+            "keyword" => Ok(DisplaySort::Keyword),
+            "score" => Ok(DisplaySort::Score),
+            _ => {
+                let message = format!(
+                    "display_sort = '{}' is not one of 'date', 'title', 'journal', 'keyword', 'score'.",
+                    display_sort
+                );
+                Err(Box::new(SettingsException(message)))
+            }
+        }?;
+
+        self.scoring = match config.get_table("scoring") {
+            Ok(table) => {
+                let default = Scoring::default();
+                Scoring {
+                    title_weight: match table.get("title_weight") {
+                        Some(value) => value.clone().into_float()? as f32,
+                        None => default.title_weight,
+                    },
+                    recency_weight: match table.get("recency_weight") {
+                        Some(value) => value.clone().into_float()? as f32,
+                        None => default.recency_weight,
+                    },
+                }
+            }
+            Err(_) => Scoring::default(),
+        };
+        Ok(())
+    }
+
+    /// The journal written for results that have none, such as conference
+    /// papers or book chapters. Empty by default.
+    /// ```
+    /// journal_placeholder = "N/A"
+    /// ```
+    fn update_journal_placeholder(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.journal_placeholder = match table.get("journal_placeholder") {
+            Some(value) => value.to_string(),
+            None => String::new(),
+        };
+        Ok(())
+    }
+
+    /// Keep only the papers of the journals that contain one of the words
+    /// of "journal_allowlist", or drop the ones of "journal_blocklist",
+    /// ignoring the case. The allowlist wins when both are set.
+    /// ```
+    /// journal_allowlist = ["Nature", "Production Economics"]
+    /// journal_blocklist = ["Predatory Journal"]
+    /// ```
+    fn update_journal_filter(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        for (key, list) in [
+            ("journal_allowlist", &mut self.journal_allowlist),
+            ("journal_blocklist", &mut self.journal_blocklist),
+        ] {
+            *list = match table.get(key) {
+                Some(value) => value
+                    .clone()
+                    .into_array()?
+                    .iter()
+                    .map(|value| value.to_string().to_lowercase())
+                    .collect(),
+                None => Vec::new(),
+            };
+        }
+        Ok(())
+    }
+
+    /// Serve "POST /run" on this port to trigger a search from other tools.
+    /// When the token is set, requests must carry the
+    /// "Authorization: Bearer <control_token>" header.
+    ///
+    /// It only listens on "127.0.0.1" unless "control_bind" says otherwise,
+    /// and an address other than the loopback requires the token.
+    /// ```
+    /// [default]
+    /// control_port = 8080
+    /// control_bind = "0.0.0.0"
+    ///
+    /// [profile]
+    /// control_token = "secret"
+    /// ```
+    fn update_control(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.control_port = match table.get("control_port") {
+            Some(value) => Some(setting_int::<u16>("control_port", value)?),
+            None => None,
+        };
+
+        self.control_bind = match table.get("control_bind") {
+            Some(value) => match value.to_string().parse::<IpAddr>() {
+                Ok(control_bind) => control_bind,
+                Err(e) => {
+                    let message = format!("control_bind = '{}' is invalid: {}", value, e);
+                    return Err(Box::new(SettingsException(message)));
+                }
+            },
+            None => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        };
+
+        let table = config.get_table("profile")?;
+        self.control_token = table.get("control_token").map(|value| value.to_string());
+        if self.control_port.is_some()
+            && !self.control_bind.is_loopback()
+            && self.control_token.is_none()
+        {
+            let message = format!(
+                "control_bind = '{}' needs control_token in [profile].",
+                self.control_bind
+            );
+            return Err(Box::new(SettingsException(message)));
+        }
+        Ok(())
+    }
+
+    /// Keep one SMTP transport and its connection across sends instead of
+    /// opening a new one for every email. Off by default.
+    ///
+    /// A failed send is tried again up to "smtp_retries" times, 3 by default,
+    /// waiting 2s, 4s, 8s, .. in between. Each attempt gives up after
+    /// "smtp_timeout_secs", 60 by default.
+    /// ```
+    /// smtp_reuse_connection = true
+    /// smtp_retries = 3
+    /// smtp_timeout_secs = 60
+    /// ```
+    fn update_smtp(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.smtp_reuse_connection = match table.get("smtp_reuse_connection") {
+            Some(value) => value.clone().into_bool()?,
+            None => false,
+        };
+        self.smtp_retries = match table.get("smtp_retries") {
+            Some(value) => setting_int::<u32>("smtp_retries", value)?,
+            None => 3,
+        };
+        let smtp_timeout = match table.get("smtp_timeout_secs") {
+            Some(value) => match setting_int::<u64>("smtp_timeout_secs", value)? {
+                0 => {
+                    let message = "smtp_timeout_secs = 0 would never wait.".to_string();
+                    return Err(Box::new(SettingsException(message)));
+                }
+                secs => Duration::from_secs(secs),
+            },
+            None => Duration::from_secs(60),
+        };
+
+        // The cached transport keeps the timeout it was built with.
+        if smtp_timeout != self.smtp_timeout {
+            self.mailer = None;
+        }
+        self.smtp_timeout = smtp_timeout;
+        Ok(())
+    }
+
+    /// Send one email per keyword with new papers instead of a single
+    /// digest, with the keyword in the subject.
+    /// ```
+    /// group_by_keyword = true
+    /// ```
+    fn update_group_by_keyword(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.group_by_keyword = match table.get("group_by_keyword") {
+            Some(value) => value.clone().into_bool()?,
+            None => false,
+        };
+        Ok(())
+    }
+
+    /// Where the papers seen so far are kept. "memory" saves them to
+    /// "state.json", and "sqlite" to "papers.db", both next to the csv file.
+    /// Read once on startup.
+    /// ```
+    /// storage_backend = ["memory", "sqlite"]
+    /// ```
+    fn update_storage_backend(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        let storage_backend = match table.get("storage_backend") {
+            Some(value) => value.to_string(),
+            None => "memory".to_string(),
+        };
+
+        self.storage_backend = match storage_backend.as_str() {
+            "memory" => Ok(StorageBackend::Memory),
+            "sqlite" => Ok(StorageBackend::Sqlite),
+            _ => {
+                let message = format!(
+                    "storage_backend = '{}' is not one of 'memory', 'sqlite'.",
+                    storage_backend
+                );
+                Err(Box::new(SettingsException(message)))
+            }
+        }?;
+        Ok(())
+    }
+
+    /// The file attached to the email. "xlsx" is an Excel workbook of the
+    /// same papers, and "both" attaches the two.
+    /// ```
+    /// attachment_format = ["csv", "xlsx", "both"]
+    /// ```
+    fn update_attachment_format(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        let attachment_format = match table.get("attachment_format") {
+            Some(value) => value.to_string(),
+            None => "csv".to_string(),
+        };
+
+        self.attachment_format = match attachment_format.as_str() {
+            "csv" => Ok(AttachmentFormat::Csv),
+            "xlsx" => Ok(AttachmentFormat::Xlsx),
+            "both" => Ok(AttachmentFormat::Both),
+            _ => {
+                let message = format!(
+                    "attachment_format = '{}' is not one of 'csv', 'xlsx', 'both'.",
+                    attachment_format
+                );
+                Err(Box::new(SettingsException(message)))
+            }
+        }?;
+        Ok(())
+    }
+
+    /// The body of the email. "html" sends a table of the papers along with
+    /// the attachments, and "plaintext" lists every paper in the text alone,
+    /// for the mail clients that show neither.
+    /// ```
+    /// body_format = ["html", "plaintext"]
+    /// ```
+    fn update_body_format(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        let body_format = match table.get("body_format") {
+            Some(value) => value.to_string(),
+            None => "html".to_string(),
+        };
+
+        self.body_format = match body_format.as_str() {
+            "html" => Ok(BodyFormat::Html),
+            "plaintext" => Ok(BodyFormat::Plaintext),
+            _ => {
+                let message = format!(
+                    "body_format = '{}' is not one of 'html', 'plaintext'.",
+                    body_format
+                );
+                Err(Box::new(SettingsException(message)))
+            }
+        }?;
+        Ok(())
+    }
+
+    /// Write "Papers.meta.json" next to the csv file with the start and end
+    /// time, the query urls, the number of papers and the failures of each
+    /// keyword in the run. Off by default.
+    /// ```
+    /// write_run_metadata = true
+    /// ```
+    fn update_run_metadata(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.write_run_metadata = match table.get("write_run_metadata") {
+            Some(value) => value.clone().into_bool()?,
+            None => false,
+        };
         Ok(())
     }
 
-    /// The regular email address string.
+    /// Crawl and parse as usual, but log the new papers instead of writing
+    /// them to the csv file and sending an email.
     /// ```
-    /// email = "zombiedelah@gmail.com"
+    /// dry_run = true
     /// ```
-    fn update_email(&mut self, config: &Config) -> Result<(), Exception> {
+    fn update_dry_run(&mut self, config: &Config) -> Result<(), Exception> {
         let table = config.get_table("default")?;
-        let email: String = table.get("email").unwrap().to_string();
-        self.email = email;
+        // "--dry-run" on the command line wins over the settings.
+        self.dry_run = match table.get("dry_run") {
+            Some(value) => value.clone().into_bool()?,
+            None => false,
+        } || DRY_RUN.load(Ordering::SeqCst);
         Ok(())
     }
 
-    /// The hour and the minute to receive the email on.
-    ///
-    /// 0 <= "HH" < 24
-    ///
-    /// 0 <= "MM" < 60
+    /// Send the email even when no new paper is found, as a heartbeat.
     /// ```
-    /// time = "HH:MM"
+    /// send_empty = true
     /// ```
-    fn update_time(&mut self, config: &Config) -> Result<(), Exception> {
+    fn update_send_empty(&mut self, config: &Config) -> Result<(), Exception> {
         let table = config.get_table("default")?;
-        let alarm_time = table.get("time").unwrap().to_string();
-
-        // Missing splicer ':'.
-        if !alarm_time.contains(':') {
-            let message = "Missing splicer ':' in the time format.".to_string();
-            return Err(Box::new(TimeFormatException((message, alarm_time))));
-        }
-
-        // Wrong format or range.
-        let (hh, mm) = alarm_time.split_once(':').unwrap();
-        self.hour = self.parse_time(hh, UnitTime::Hour)?;
-        self.minute = self.parse_time(mm, UnitTime::Minute)?;
+        self.send_empty = match table.get("send_empty") {
+            Some(value) => value.clone().into_bool()?,
+            None => false,
+        };
         Ok(())
     }
 
-    fn parse_time(&mut self, time_str: &str, ut: UnitTime) -> Result<u32, Exception> {
-        match ut {
-            UnitTime::Hour => {
-                let hour = time_str.parse::<u32>()?;
-                if hour >= 24 {
-                    let message = "Set hour between 0 <= 'HH' < 24".to_string();
-                    return Err(Box::new(TimeFormatException((message, hour.to_string()))));
-                }
+    /// Send a short email with the error when a run fails, at most once in
+    /// "error_notify_hours".
+    /// ```
+    /// error_notify = true
+    /// error_notify_hours = 24
+    /// ```
+    fn update_error_notify(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        self.error_notify = match table.get("error_notify") {
+            Some(value) => value.clone().into_bool()?,
+            None => false,
+        };
+        self.error_notify_hours = match table.get("error_notify_hours") {
+            Some(value) => setting_int::<u32>("error_notify_hours", value)?,
+            None => 24,
+        };
+        Ok(())
+    }
 
-                Ok(hour)
-            }
-            UnitTime::Minute => {
-                let minute = time_str.parse::<u32>()?;
-                if minute >= 60 {
-                    let message = "Set minute between 0 <= 'MM' < 60".to_string();
-                    return Err(Box::new(TimeFormatException((message, minute.to_string()))));
-                }
+    /// "overwrite" starts the csv file over on every run, and "append" adds
+    /// the papers of each run to the end, as a log of every run.
+    /// "timestamped_output" writes each run to its own file instead, named
+    /// "Papers_YYYYMMDD_HHMM.csv" after the time of the run.
+    /// ```
+    /// csv_mode = ["overwrite", "append"]
+    /// timestamped_output = true
+    /// ```
+    fn update_csv_mode(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = config.get_table("default")?;
+        let csv_mode = match table.get("csv_mode") {
+            Some(value) => value.to_string(),
+            None => "overwrite".to_string(),
+        };
 
-                Ok(minute)
+        self.csv_mode = match csv_mode.as_str() {
+            "overwrite" => Ok(CsvMode::Overwrite),
+            "append" => Ok(CsvMode::Append),
+            _ => {
+                let message = format!(
+                    "csv_mode = '{}' is not one of 'overwrite', 'append'.",
+                    csv_mode
+                );
+                Err(Box::new(SettingsException(message)))
             }
-        }
+        }?;
+        self.timestamped_output = match table.get("timestamped_output") {
+            Some(value) => value.clone().into_bool()?,
+            None => false,
+        };
+        Ok(())
     }
 
-    /// Choose one of the weekday to receive an email on.
+    /// Also write the new papers of each run as a JSON array to this path.
     /// ```
-    /// weekday = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"]
+    /// json_output_path = "Papers.json"
     /// ```
-    fn update_weekday(&mut self, config: &Config) -> Result<(), Exception> {
+    fn update_json_output(&mut self, config: &Config) -> Result<(), Exception> {
         let table = config.get_table("default")?;
-        let weekday_value = table.get("weekday").unwrap().to_string();
+        self.json_output_path = table.get("json_output_path").map(|value| value.to_string());
+        Ok(())
+    }
 
-        self.weekday = match weekday_value.as_str() {
-            "Mon" => Ok(Weekday::Mon),
-            "Tue" => Ok(Weekday::Tue),
-            "Wed" => Ok(Weekday::Wed),
-            "Thu" => Ok(Weekday::Thu),
-            "Fri" => Ok(Weekday::Fri),
-            "Sat" => Ok(Weekday::Sat),
-            "Sun" => Ok(Weekday::Sun),
-            _ => Err(Box::new(WeekdayException(weekday_value))),
-        }?;
+    /// Show the browser window instead of running headless, to watch what
+    /// the crawler sees. "width" and "height" are set together. It is read
+    /// once at startup.
+    ///
+    /// "user_agent" replaces the built-in user agent, and "user_agents" is a
+    /// pool that is taken in turns, one for each result page.
+    ///
+    /// "proxy" is the proxy server the browser connects through, except for
+    /// the hosts in "proxy_bypass".
+    ///
+    /// Between the runs the tab is replaced with a fresh one every
+    /// "keep_alive_secs" seconds so the session does not time out, and the
+    /// new tab opens "keep_alive_url" when it is set.
+    ///
+    /// "consent_selector" is the "Accept" button of the cookie consent
+    /// banner, clicked before the results are read. An empty one turns it off.
+    ///
+    /// "language" is sent as the "Accept-Language" header, so the results
+    /// come back in that language whatever the site guesses from the IP.
+    /// ```
+    /// [browser]
+    /// headless = false
+    /// width = 1280
+    /// height = 1024
+    /// user_agent = "Mozilla/5.0 ..."
+    /// user_agents = ["Mozilla/5.0 ...", "Mozilla/5.0 ..."]
+    /// proxy = "http://proxy.example.com:8080"
+    /// proxy_bypass = ["localhost", "*.example.com"]
+    /// keep_alive_secs = 60
+    /// keep_alive_url = "https://www.sciencedirect.com/"
+    /// consent_selector = "#onetrust-accept-btn-handler"
+    /// language = "en-US,en"
+    /// ```
+    fn update_browser(&mut self, config: &Config) -> Result<(), Exception> {
+        let table = match config.get_table("browser") {
+            Ok(table) => table,
+            Err(_) => Map::new(),
+        };
+        self.headless = match table.get("headless") {
+            Some(value) => value.clone().into_bool()?,
+            None => true,
+        };
+        self.window_size = match (table.get("width"), table.get("height")) {
+            (Some(width), Some(height)) => Some((
+                setting_int::<u32>("width", width)?,
+                setting_int::<u32>("height", height)?,
+            )),
+            (None, None) => None,
+            _ => {
+                let message = "Set both 'width' and 'height' in [browser].".to_string();
+                return Err(Box::new(SettingsException(message)));
+            }
+        };
+        self.user_agent = table.get("user_agent").map(|value| value.to_string());
+        self.user_agents = match table.get("user_agents") {
+            Some(value) => value
+                .clone()
+                .into_array()?
+                .iter()
+                .map(|value| value.to_string())
+                .collect(),
+            None => Vec::new(),
+        };
+        self.keep_alive_secs = match table.get("keep_alive_secs") {
+            Some(value) => setting_int::<u64>("keep_alive_secs", value)?,
+            None => 60,
+        };
+        self.keep_alive_url = table.get("keep_alive_url").map(|value| value.to_string());
+        self.consent_selector = table.get("consent_selector").map(|value| value.to_string());
+        self.language = match table.get("language") {
+            Some(value) if value.to_string().trim().is_empty() => {
+                let message = "'language' in [browser] is empty.".to_string();
+                return Err(Box::new(SettingsException(message)));
+            }
+            Some(value) => Some(value.to_string()),
+            None => None,
+        };
+        self.proxy = table.get("proxy").map(|value| value.to_string());
+        self.proxy_bypass = match table.get("proxy_bypass") {
+            Some(value) => value
+                .clone()
+                .into_array()?
+                .iter()
+                .map(|value| value.to_string())
+                .collect(),
+            None => Vec::new(),
+        };
         Ok(())
     }
 
     /// /// # Warning
     /// Never upload the "Settings.toml" file with user id and password!
     ///
+    /// "smtp_host" is "smtp.naver.com" when it is not set, with the default
+    /// port of the relay and "{id}@naver.com" as the sender. The domain of
+    /// the sender follows "smtp_host", as "{id}@gmail.com" for
+    /// "smtp.gmail.com", and an id that is already an address is used as is.
+    /// "smtp_encryption" is "tls" (implicit TLS, port 465) by default,
+    /// "starttls" (port 587) or "none" for a server without encryption.
+    ///
+    /// The sender is "from_name <from_email>", "Crawler" and "{id}@{domain}"
+    /// by default. "from_address" sets the whole sender at once instead.
     /// ```
     /// id = "user id"
     /// password = "user password"
+    /// smtp_host = "smtp.gmail.com"
+    /// smtp_port = 465
+    /// smtp_encryption = ["tls", "starttls", "none"]
+    /// from_name = "Paper Alert"
+    /// from_email = "user@gmail.com"
     /// ```
     fn update_profile(&mut self, config: &Config) -> Result<(), Exception> {
         let table = config.get_table("profile")?;
@@ -320,134 +2543,814 @@ impl Settings {
             return Err(Box::new(ProfileException(message)));
         }
 
-        if self.mailer.is_none() {
-            // Set credentials for SMTP protocol.
-            let credentials = Credentials::new(id.to_string(), password);
+        let smtp_host = match table.get("smtp_host") {
+            Some(value) => value.to_string(),
+            None => "smtp.naver.com".to_string(),
+        };
+        let smtp_port = match table.get("smtp_port") {
+            Some(value) => {
+                let port = value.clone().into_int()?;
+                match u16::try_from(port) {
+                    Ok(port) if port > 0 => Some(port),
+                    _ => {
+                        let message = format!("smtp_port = {} is not in 1..=65535.", port);
+                        return Err(Box::new(ProfileException(message)));
+                    }
+                }
+            }
+            None => None,
+        };
+        let smtp_encryption = match table.get("smtp_encryption") {
+            Some(value) => match value.to_string().as_str() {
+                "tls" => SmtpEncryption::Tls,
+                "starttls" => SmtpEncryption::StartTls,
+                "none" => SmtpEncryption::None,
+                other => {
+                    let message = format!(
+                        "smtp_encryption = '{}' is not one of 'tls', 'starttls', 'none'.",
+                        other
+                    );
+                    return Err(Box::new(ProfileException(message)));
+                }
+            },
+            None => SmtpEncryption::Tls,
+        };
 
-            // Open a remote connection to naver SMTP server.
-            self.mailer = Some(
-                SmtpTransport::relay("smtp.naver.com")?
-                    .credentials(credentials)
-                    .build(),
+        // Port 465 only speaks implicit TLS and port 587 expects STARTTLS,
+        // so these pairs would only fail at the first email.
+        let mismatch = match (smtp_encryption, smtp_port) {
+            (SmtpEncryption::StartTls, Some(465)) => Some("use smtp_encryption = 'tls'"),
+            (SmtpEncryption::None, Some(465)) => Some("use smtp_encryption = 'tls'"),
+            (SmtpEncryption::Tls, Some(587)) => Some("use smtp_encryption = 'starttls'"),
+            _ => None,
+        };
+        if let (Some(hint), Some(port)) = (mismatch, smtp_port) {
+            let message = format!(
+                "smtp_encryption = '{}' does not work on smtp_port = {}, {}.",
+                smtp_encryption, port, hint
             );
+            return Err(Box::new(ProfileException(message)));
         }
+        self.from_address = table.get("from_address").map(|value| value.to_string());
+        self.from_name = table.get("from_name").map(|value| value.to_string());
+        self.from_email = table.get("from_email").map(|value| value.to_string());
 
+        // Drop the cached transport when it is turned off or the server or
+        // the credentials have changed.
+        if !self.smtp_reuse_connection
+            || id != self.id
+            || password != self.password
+            || smtp_host != self.smtp_host
+            || smtp_port != self.smtp_port
+            || smtp_encryption != self.smtp_encryption
+        {
+            self.mailer = None;
+        }
         self.id = id;
+        self.password = password;
+        self.smtp_host = smtp_host;
+        self.smtp_port = smtp_port;
+        self.smtp_encryption = smtp_encryption;
+
+        // A bad sender is reported now rather than when the email is sent.
+        self.from_address()?;
+        if self.smtp_reuse_connection && self.mailer.is_none() {
+            self.mailer = Some(self.build_mailer()?);
+        }
         Ok(())
     }
 
-    /// Send an email.
-    fn send_email(&self, local_time: &str) -> Result<(), Exception> {
-        // Set the csv file.
-        let file_name = "Papers.csv".to_string();
-        let file_body = fs::read(load_csv_path()?)?;
-        let content_type = ContentType::parse("text/csv")?;
-        let attachment = Attachment::new(file_name).body(file_body, content_type);
+    /// Open a remote connection to the SMTP server.
+    fn build_mailer(&self) -> Result<SmtpTransport, Exception> {
+        // Set credentials for SMTP protocol.
+        let credentials = Credentials::new(self.id.to_string(), self.password.to_string());
+        let builder = match self.smtp_encryption {
+            SmtpEncryption::Tls => SmtpTransport::relay(&self.smtp_host)?,
+            SmtpEncryption::StartTls => SmtpTransport::starttls_relay(&self.smtp_host)?,
+            SmtpEncryption::None => SmtpTransport::builder_dangerous(&self.smtp_host),
+        };
+        let mut builder = builder
+            .credentials(credentials)
+            .timeout(Some(self.smtp_timeout));
+        if let Some(port) = self.smtp_port {
+            builder = builder.port(port);
+        }
+        Ok(builder.build())
+    }
 
-        // Build the message block.
-        let email = self.email.clone();
+    /// The sender of the email, "Crawler <{id}@{domain}>" by default, with the
+    /// domain taken from "smtp_host".
+    fn from_address(&self) -> Result<Mailbox, Exception> {
+        if let Some(from_address) = &self.from_address {
+            return match from_address.parse() {
+                Ok(mailbox) => Ok(mailbox),
+                Err(e) => {
+                    let message = format!("from_address = '{}' is invalid: {}", from_address, e);
+                    Err(Box::new(ProfileException(message)))
+                }
+            };
+        }
+
+        let from_name = match &self.from_name {
+            Some(from_name) => from_name.to_string(),
+            None => "Crawler".to_string(),
+        };
+        let from_email = match &self.from_email {
+            Some(from_email) => from_email.to_string(),
+            None if self.id.contains('@') => self.id.clone(),
+            None => format!("{}@{}", &self.id, from_domain(&self.smtp_host)),
+        };
+        match from_email.parse::<Address>() {
+            Ok(address) => Ok(Mailbox::new(Some(from_name), address)),
+            Err(e) => {
+                let message = format!("from_email = '{}' is invalid: {}", from_email, e);
+                Err(Box::new(ProfileException(message)))
+            }
+        }
+    }
+
+    /// Send a plain text email with the error of a failed run.
+    fn send_error_email(&self, error: &str, now: NaiveDateTime) -> Result<(), Exception> {
+        let body = format!(
+            "The crawler failed to search at [{}]:\n\n{}\n",
+            now.format("%Y-%m-%d %H:%M:%S"),
+            error
+        );
         let message = Message::builder()
-            .from(format!("Crawler <{}@naver.com>", &self.id).parse().unwrap())
-            .to(email.parse().unwrap())
-            .subject("SMTP Test")
-            .singlepart(attachment)?;
+            .from(self.from_address()?)
+            .to(self.email.parse()?)
+            .subject("linkdrive-rs: the search failed")
+            .singlepart(SinglePart::plain(body))?;
 
-        let mailer = self.mailer.as_ref().unwrap();
-        match mailer.send(&message) {
-            Ok(_) => {
-                println!("Message sent at [{}]", local_time);
+        let fresh;
+        let mailer = match &self.mailer {
+            Some(mailer) => mailer,
+            None => {
+                fresh = self.build_mailer()?;
+                &fresh
             }
+        };
+        mailer.send(&message)?;
+        tracing::info!("Error report sent at [{}]", now);
+        Ok(())
+    }
+
+    /// Send a fixed message, with the csv file at "csv_path" attached when
+    /// it is set, right away and without retries. Returns the response of
+    /// the server.
+    pub fn send_test_email(&self, csv_path: Option<&Path>) -> Result<String, Exception> {
+        let body = format!(
+            "This is a test email from linkdrive-rs, sent through {} at [{}].\n",
+            self.smtp_host,
+            now_in(self.timezone).format("%Y-%m-%d %H:%M:%S")
+        );
+        let mut multipart = MultiPart::mixed().singlepart(SinglePart::plain(body));
+        if let Some(csv_path) = csv_path {
+            let file_name = match csv_path.file_name() {
+                Some(file_name) => file_name.to_string_lossy().to_string(),
+                None => "Papers.csv".to_string(),
+            };
+            let content_type = ContentType::parse("text/csv")?;
+            multipart = multipart
+                .singlepart(Attachment::new(file_name).body(fs::read(csv_path)?, content_type));
+        }
+        let message = Message::builder()
+            .from(self.from_address()?)
+            .to(self.email.parse()?)
+            .subject("linkdrive-rs: test email")
+            .multipart(multipart)?;
+
+        match self.build_mailer()?.send(&message) {
+            Ok(response) => Ok(format!(
+                "{} {}",
+                response.code(),
+                response.message().collect::<Vec<&str>>().join(" ")
+            )),
             Err(e) => {
-                dbg!(e);
+                let message = format!(
+                    "Failed to send the test email through {}: {}",
+                    self.smtp_host, e
+                );
+                Err(Box::new(EmailException(message)))
             }
         }
-        Ok(())
     }
-}
 
-pub struct TimeFormatException((String, String));
+    /// Send an email with the csv file at "csv_path". A full snapshot is
+    /// labeled as such in the subject.
+    ///
+    /// The message is built with the attachments read into memory, so the
+    /// csv file may be replaced at once, and sent on a thread of its own so
+    /// the crawler does not wait for the SMTP server. The thread tries
+    /// "smtp_retries" more times before it gives up with the error.
+    fn send_email(
+        &self,
+        local_time: &str,
+        papers: &[Paper],
+        snapshot: bool,
+        csv_path: Option<&Path>,
+        truncated: usize,
+    ) -> Result<JoinHandle<Result<(), String>>, Exception> {
+        // Set the attached files. A plain text email has the papers in the
+        // body instead.
+        let mut attachments = Vec::<SinglePart>::new();
+        let attach = self.body_format == BodyFormat::Html;
+        if attach
+            && matches!(
+                self.attachment_format,
+                AttachmentFormat::Csv | AttachmentFormat::Both
+            )
+        {
+            let file_name = match csv_path.and_then(Path::file_name) {
+                Some(file_name) => file_name.to_string_lossy().to_string(),
+                None => "Papers.csv".to_string(),
+            };
+            // An appended file holds every run, and a keyword email only
+            // its own papers, so those are attached from memory.
+            let file_body = match (csv_path, self.csv_mode) {
+                (Some(csv_path), CsvMode::Overwrite) => fs::read(csv_path)?,
+                _ => csv_buffer(papers)?,
+            };
+            let content_type = ContentType::parse("text/csv")?;
+            attachments.push(Attachment::new(file_name).body(file_body, content_type));
+        }
+        if attach
+            && matches!(
+                self.attachment_format,
+                AttachmentFormat::Xlsx | AttachmentFormat::Both
+            )
+        {
+            let file_name = "Papers.xlsx".to_string();
+            let file_body = xlsx_buffer(papers)?;
+            let content_type = ContentType::parse(
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            )?;
+            attachments.push(Attachment::new(file_name).body(file_body, content_type));
+        }
 
-impl Debug for TimeFormatException {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let buffer = format!(
-            "\n\t{}\n\
-            \ttime = {} is not a valid time format.\n\
-            \ttime = 'HH:MM' is the valid format.",
-            &self.0 .0, &self.0 .1
-        );
-        write!(f, "{}", buffer)
+        // Render the subject and the body from the template.
+        let date = now_in(self.timezone).format("%Y-%m-%d").to_string();
+        let keywords = papers
+            .iter()
+            .map(|paper| paper.keyword.as_str())
+            .collect::<BTreeSet<&str>>()
+            .into_iter()
+            .collect::<Vec<&str>>()
+            .join(", ");
+        let mut subject = self.template.render_subject(papers.len(), &date, &keywords);
+        if self.group_by_keyword && !papers.is_empty() {
+            subject = format!("[{}] {}", keywords, subject);
+        }
+        if snapshot {
+            subject = format!("[Full snapshot] {}", subject);
+        }
+        let mut body = match self.body_format {
+            BodyFormat::Html => {
+                self.template
+                    .render_body(papers, self.max_body_entries, papers.len(), &date)
+            }
+            BodyFormat::Plaintext => {
+                self.template
+                    .render_plaintext(papers, self.max_body_entries, &date)
+            }
+        };
+        let mut html = build_html(&subject, papers, self.max_body_entries);
+        if truncated > 0 {
+            let note = format!(
+                "The results were truncated at max_papers_per_run = {}. \
+                {} more papers are left for the next run.",
+                self.max_papers_per_run.unwrap_or_default(),
+                truncated
+            );
+            body.push_str(&format!("\n{}\n", note));
+            html = html.replace(
+                "</body></html>",
+                &format!("<p>{}</p>\n</body></html>", note),
+            );
+        }
+
+        // Build the message block.
+        let email = self.email.clone();
+        let builder = Message::builder()
+            .from(self.from_address()?)
+            .to(email.parse()?)
+            .subject(subject);
+        let message = match self.body_format {
+            BodyFormat::Html => {
+                let mut multipart = MultiPart::mixed().multipart(
+                    MultiPart::alternative()
+                        .singlepart(SinglePart::plain(body))
+                        .singlepart(SinglePart::html(html)),
+                );
+                for attachment in attachments {
+                    multipart = multipart.singlepart(attachment);
+                }
+                builder.multipart(multipart)?
+            }
+            BodyFormat::Plaintext => builder.singlepart(SinglePart::plain(body))?,
+        };
+
+        // Reuse the cached transport, or build one for this send only.
+        let mailer = match &self.mailer {
+            Some(mailer) => mailer.clone(),
+            None => self.build_mailer()?,
+        };
+        let local_time = local_time.to_string();
+        let smtp_retries = self.smtp_retries;
+        let handle = thread::spawn(move || {
+            let mut retry = 0;
+            loop {
+                match mailer.send(&message) {
+                    Ok(_) => {
+                        tracing::info!("Message sent at [{}]", local_time);
+                        return Ok(());
+                    }
+                    Err(e) if retry < smtp_retries => {
+                        let backoff = Duration::from_secs(2u64.saturating_pow(retry + 1));
+                        retry += 1;
+                        tracing::warn!(
+                            "Failed to send the email ({}/{}), retrying in {}s: {}",
+                            retry,
+                            smtp_retries,
+                            backoff.as_secs(),
+                            e
+                        );
+                        thread::sleep(backoff);
+                    }
+                    Err(e) => {
+                        let message =
+                            format!("Failed to send the email of [{}]: {}", local_time, e);
+                        tracing::error!("{}", message);
+                        return Err(message);
+                    }
+                }
+            }
+        });
+        Ok(handle)
     }
 }
 
-impl Display for TimeFormatException {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let buffer = format!(
-            "\n\t{}\n\
-            \ttime = {} is not a valid time format.\n\
-            \ttime = 'HH:MM' is the valid format.",
-            &self.0 .0, &self.0 .1
-        );
-        write!(f, "{}", buffer)
-    }
+/// The order of papers in the email and the csv file.
+#[derive(Clone, Copy)]
+pub enum DisplaySort {
+    Date,
+    Title,
+    Journal,
+    Keyword,
+    Score,
 }
 
-impl Error for TimeFormatException {}
+/// Where the papers seen so far are kept.
+#[derive(Clone, Copy)]
+pub enum StorageBackend {
+    Memory,
+    Sqlite,
+}
 
-pub struct WeekdayException(String);
+/// Where the keyword is looked for in a paper.
+#[derive(Clone, Copy)]
+pub enum SearchField {
+    All,
+    Title,
+}
 
-impl Debug for WeekdayException {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "\n\tweekday = '{}' is not a valid weekday format.\nChoose from\n\
-            \t'Mon'\n\
-            \t'Tue'\n\
-            \t'Wed'\n\
-            \t'Thu'\n\
-            \t'Fri'\n\
-            \t'Sat'\n\
-            \t'Sun'\n",
-            &self.0
-        )
-    }
+/// The order of the search results.
+#[derive(Clone, Copy)]
+pub enum SortBy {
+    Date,
+    Relevance,
 }
 
-impl Display for WeekdayException {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "\n\tweekday = '{}' is not a valid weekday format.\nChoose from\n\
-            \t'Mon'\n\
-            \t'Tue'\n\
-            \t'Wed'\n\
-            \t'Thu'\n\
-            \t'Fri'\n\
-            \t'Sat'\n\
-            \t'Sun'\n",
-            &self.0
-        )
-    }
+/// Whether the csv file is started over or appended to on every run.
+#[derive(Clone, Copy, PartialEq)]
+pub enum CsvMode {
+    Overwrite,
+    Append,
 }
 
-impl Error for WeekdayException {}
+/// The file attached to the email.
+#[derive(Clone, Copy)]
+pub enum AttachmentFormat {
+    Csv,
+    Xlsx,
+    Both,
+}
 
-pub struct ProfileException(String);
+/// The body of the email.
+#[derive(Clone, Copy, PartialEq)]
+pub enum BodyFormat {
+    Html,
+    Plaintext,
+}
 
-impl Debug for ProfileException {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "\n\t{}", &self.0)
-    }
+/// How the connection to the SMTP server is encrypted.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SmtpEncryption {
+    Tls,
+    StartTls,
+    None,
 }
 
-impl Display for ProfileException {
+impl std::fmt::Display for SmtpEncryption {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "\n\t{}", &self.0)
+        match self {
+            SmtpEncryption::Tls => write!(f, "tls"),
+            SmtpEncryption::StartTls => write!(f, "starttls"),
+            SmtpEncryption::None => write!(f, "none"),
+        }
     }
 }
 
-impl Error for ProfileException {}
-
 pub enum UnitTime {
     Hour,
     Minute,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_time_format_exception(result: Result<(u32, u32), Exception>) -> bool {
+        match result {
+            Ok(_) => false,
+            Err(e) => e.downcast_ref::<TimeFormatException>().is_some(),
+        }
+    }
+
+    #[test]
+    fn parses_valid_times() {
+        let valid = [
+            ("00:00", (0, 0)),
+            ("08:30", (8, 30)),
+            ("23:59", (23, 59)),
+            ("9:5", (9, 5)),
+            ("09:05", (9, 5)),
+            ("007:00", (7, 0)),
+        ];
+        for (alarm_time, expected) in valid {
+            let parsed = Settings::parse_alarm_time(alarm_time.to_string()).unwrap();
+            assert_eq!(parsed, expected, "{}", alarm_time);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_times() {
+        let invalid = [
+            "0830", "", "24:00", "23:60", "99:99", "-1:00", "08:", ":30", "ab:30", "08:3o",
+            "08:30:00", " 08:30",
+        ];
+        for alarm_time in invalid {
+            let result = Settings::parse_alarm_time(alarm_time.to_string());
+            assert!(is_time_format_exception(result), "{}", alarm_time);
+        }
+    }
+
+    #[test]
+    fn parses_every_weekday_code() {
+        let codes = [
+            ("Mon", Weekday::Mon),
+            ("Tue", Weekday::Tue),
+            ("Wed", Weekday::Wed),
+            ("Thu", Weekday::Thu),
+            ("Fri", Weekday::Fri),
+            ("Sat", Weekday::Sat),
+            ("Sun", Weekday::Sun),
+        ];
+        for (code, weekday) in codes {
+            assert_eq!(Settings::parse_weekday(code.into()).unwrap(), vec![weekday]);
+        }
+        assert_eq!(Settings::parse_weekday("daily".into()).unwrap().len(), 7);
+        assert_eq!(Settings::parse_weekday("weekday".into()).unwrap().len(), 5);
+        assert_eq!(
+            Settings::parse_weekday("weekend".into()).unwrap(),
+            vec![Weekday::Sat, Weekday::Sun]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_weekdays() {
+        for code in ["mon", "Monday", "MON", "Mo", "", "weekdays"] {
+            let e = Settings::parse_weekday(code.into()).unwrap_err();
+            assert!(e.downcast_ref::<WeekdayException>().is_some(), "{}", code);
+        }
+    }
+
+    fn paper(keyword: &str, title: &str, abstract_text: Option<&str>) -> Paper {
+        Paper {
+            keyword: keyword.to_string(),
+            title: title.to_string(),
+            authors: Vec::new(),
+            journal: String::new(),
+            published: None,
+            href: "https://example.com/pii/1".to_string(),
+            doi: None,
+            abstract_text: abstract_text.map(str::to_string),
+            score: 0.0,
+            match_location: MatchLocation::Neither,
+        }
+    }
+
+    /// Three papers that each sort key puts in a different order.
+    fn unsorted() -> Vec<Paper> {
+        let mut first = paper("graphene", "Beta", None);
+        first.journal = "Carbon".to_string();
+        first.published = Some("15 June 2023".to_string());
+        first.score = 1.0;
+        let mut second = paper("ai", "Gamma", None);
+        second.journal = "Automatica".to_string();
+        second.published = None;
+        second.score = 3.0;
+        let mut third = paper("battery", "Alpha", None);
+        third.journal = "Batteries".to_string();
+        third.published = Some("March 2023".to_string());
+        third.score = 2.0;
+        vec![first, second, third]
+    }
+
+    fn sorted_titles(display_sort: DisplaySort) -> Vec<String> {
+        let mut papers = unsorted();
+        sort_papers(&mut papers, display_sort);
+        papers.into_iter().map(|paper| paper.title).collect()
+    }
+
+    #[test]
+    fn sorts_by_date_with_undated_last() {
+        assert_eq!(sorted_titles(DisplaySort::Date), ["Beta", "Alpha", "Gamma"]);
+    }
+
+    #[test]
+    fn cap_keeps_the_newest_papers_across_keywords() {
+        let mut papers = unsorted()
+            .into_iter()
+            .zip([0, 0, 1])
+            .map(|(paper, rank)| (rank, paper))
+            .collect::<Vec<_>>();
+        assert_eq!(cap_papers(&mut papers, 2), 1);
+        let kept = papers
+            .iter()
+            .map(|(rank, paper)| (*rank, paper.title.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(kept, [(0, "Beta"), (1, "Alpha")]);
+    }
+
+    #[test]
+    fn sorts_by_title() {
+        assert_eq!(
+            sorted_titles(DisplaySort::Title),
+            ["Alpha", "Beta", "Gamma"]
+        );
+    }
+
+    #[test]
+    fn sorts_by_journal() {
+        assert_eq!(
+            sorted_titles(DisplaySort::Journal),
+            ["Gamma", "Alpha", "Beta"]
+        );
+    }
+
+    #[test]
+    fn sorts_by_keyword() {
+        assert_eq!(
+            sorted_titles(DisplaySort::Keyword),
+            ["Gamma", "Alpha", "Beta"]
+        );
+    }
+
+    #[test]
+    fn sorts_by_score() {
+        assert_eq!(
+            sorted_titles(DisplaySort::Score),
+            ["Gamma", "Alpha", "Beta"]
+        );
+    }
+
+    #[test]
+    fn doi_is_always_quoted_in_the_csv_file() {
+        let mut doi_paper = paper("ai", "A title", None);
+        doi_paper.doi = Some("10.1016/0001-2345(89)90012-3".to_string());
+        let buffer = String::from_utf8(csv_buffer(&[doi_paper]).unwrap()).unwrap();
+        let row = buffer.lines().nth(1).unwrap();
+        assert_eq!(
+            row,
+            "ai,A title,,,,https://example.com/pii/1,\"10.1016/0001-2345(89)90012-3\",,neither"
+        );
+    }
+
+    #[test]
+    fn href_drops_tracking_params() {
+        let href = "https://www.sciencedirect.com/science/article/pii/S0925527323001234";
+        assert_eq!(normalize_href(href), href);
+        assert_eq!(
+            normalize_href(&format!("{}?via%3Dihub&utm_source=alert", href)),
+            href
+        );
+        assert_eq!(normalize_href(&format!("{}#abs0010", href)), href);
+    }
+
+    #[test]
+    fn href_ignores_param_order() {
+        let href = "https://www.sciencedirect.com/science/article/pii/S0925527323001234";
+        assert_eq!(
+            normalize_href(&format!("{}?dgcid=rss&via=ihub", href)),
+            normalize_href(&format!("{}?via=ihub&dgcid=rss", href))
+        );
+    }
+
+    #[test]
+    fn href_drops_the_trailing_slash() {
+        let href = "https://www.sciencedirect.com/science/article/pii/S0925527323001234";
+        assert_eq!(normalize_href(&format!("{}/", href)), href);
+        assert_eq!(normalize_href(&format!("{}/?via=ihub", href)), href);
+    }
+
+    #[test]
+    fn score_counts_the_words_of_or_groups_and_phrases() {
+        let scoring = Scoring {
+            title_weight: 1.0,
+            recency_weight: 0.0,
+        };
+        let grouped = paper(
+            "graphene OR \"lithium ion\"",
+            "Graphene or lithium ion cells",
+            None,
+        );
+        assert_eq!(score_paper(&grouped, 0, &scoring), 3.0);
+        let plain = paper("supply chain", "Supply chain of the supply", None);
+        assert_eq!(score_paper(&plain, 0, &scoring), 3.0);
+    }
+
+    #[test]
+    fn published_since_is_day_granular() {
+        let since = NaiveDate::from_ymd_opt(2026, 9, 1).unwrap();
+        let mut dated = paper("ai", "A title", None);
+        dated.published = Some("31 August 2026".to_string());
+        assert!(!dated.published_since(since));
+        dated.published = Some("1 September 2026".to_string());
+        assert!(dated.published_since(since));
+        dated.published = Some("January 2026".to_string());
+        assert!(!dated.published_since(since));
+        dated.published = None;
+        assert!(!dated.published_since(since));
+    }
+
+    #[test]
+    fn locates_the_keyword() {
+        let in_title = paper("Supply Chain", "Supply chain resilience", Some("Ports."));
+        assert_eq!(in_title.locate_keyword(), MatchLocation::Title);
+
+        let in_abstract = paper(
+            "supply chain",
+            "Port congestion",
+            Some("The chain of supply."),
+        );
+        assert_eq!(in_abstract.locate_keyword(), MatchLocation::Abstract);
+
+        let alternative = paper("graphene OR \"lithium ion\"", "Graphite anodes", None);
+        assert_eq!(alternative.locate_keyword(), MatchLocation::Neither);
+        let alternative = paper("graphene OR \"lithium ion\"", "Ion flow in lithium", None);
+        assert_eq!(alternative.locate_keyword(), MatchLocation::Title);
+
+        let elsewhere = paper("supply chain", "Port congestion", None);
+        assert_eq!(elsewhere.locate_keyword(), MatchLocation::Neither);
+    }
+
+    fn keyword_config(keyword: &str) -> Config {
+        let toml = format!("[default]\nkeyword = {}\n", keyword);
+        Config::builder()
+            .add_source(config::File::from_str(&toml, config::FileFormat::Toml))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn run_without_papers_leaves_a_csv_file_with_the_header() {
+        let dir = std::env::temp_dir().join(format!("linkdrive-csv-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let csv_path = dir.join("Papers.csv");
+        let _ = fs::remove_file(&csv_path);
+
+        ensure_csv_header(&csv_path).unwrap();
+        let header = fs::read_to_string(&csv_path).unwrap();
+        assert_eq!(header.lines().count(), 1);
+        assert!(header.starts_with(Paper::COLUMNS[0]), "{}", header);
+
+        // The papers of the last run are kept.
+        let last_run = format!("{}a,b\n", header);
+        fs::write(&csv_path, &last_run).unwrap();
+        ensure_csv_header(&csv_path).unwrap();
+        assert_eq!(fs::read_to_string(&csv_path).unwrap(), last_run);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn negative_setting_names_the_key() {
+        let toml = "[default]\nmax_pages = -1\n";
+        let config = Config::builder()
+            .add_source(config::File::from_str(toml, config::FileFormat::Toml))
+            .build()
+            .unwrap();
+        let mut settings = Settings::defaults();
+        let e = settings.update_pages(&config).unwrap_err();
+        assert!(e.downcast_ref::<SettingsException>().is_some());
+        assert!(
+            e.to_string().contains("'max_pages' is out of range: -1"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn duplicate_keywords_are_searched_once() {
+        let mut settings = Settings::defaults();
+        let config = keyword_config(r#"["ai", "graphene", "ai", { term = "graphene" }]"#);
+        settings.update_keyword(&config).unwrap();
+        assert_eq!(
+            settings.keyword,
+            HashSet::from(["ai".to_string(), "graphene".to_string()])
+        );
+    }
+
+    #[test]
+    fn conflicting_duplicate_keywords_are_rejected() {
+        for keyword in [
+            r#"["ai", { term = "ai", weekday = "Mon" }]"#,
+            r#"[{ term = "ai", limit = 10 }, { term = "ai", limit = 20 }]"#,
+        ] {
+            let mut settings = Settings::defaults();
+            let e = settings
+                .update_keyword(&keyword_config(keyword))
+                .unwrap_err();
+            assert!(
+                e.downcast_ref::<SettingsException>().is_some(),
+                "{}",
+                keyword
+            );
+        }
+    }
+
+    #[test]
+    fn keyword_changes_are_sorted() {
+        let previous = HashSet::from(["ai".to_string(), "battery".to_string()]);
+        let current = HashSet::from([
+            "battery".to_string(),
+            "graphene".to_string(),
+            "catalyst".to_string(),
+        ]);
+        let (added, removed) = keyword_changes(&previous, &current);
+        assert_eq!(added, vec!["catalyst", "graphene"]);
+        assert_eq!(removed, vec!["ai"]);
+    }
+
+    #[test]
+    fn or_group_joins_the_keywords() {
+        let alias_list = vec!["graphene".to_string(), "graphite".to_string()];
+        assert_eq!(or_group(&alias_list), "graphene OR graphite");
+    }
+
+    #[test]
+    fn or_group_quotes_phrases() {
+        let alias_list = vec![
+            "lithium  ion".to_string(),
+            "sodium ion".to_string(),
+            " ".to_string(),
+        ];
+        assert_eq!(or_group(&alias_list), "\"lithium ion\" OR \"sodium ion\"");
+        assert_eq!(or_group(&["supply chain".to_string()]), "supply chain");
+    }
+
+    #[test]
+    fn from_domain_of_common_hosts() {
+        assert_eq!(from_domain("smtp.naver.com"), "naver.com");
+        assert_eq!(from_domain("smtp.gmail.com"), "gmail.com");
+        assert_eq!(from_domain("smtp.googlemail.com"), "gmail.com");
+        assert_eq!(from_domain("smtp.daum.net"), "daum.net");
+        assert_eq!(from_domain("smtp.mail.yahoo.com"), "yahoo.com");
+        assert_eq!(from_domain("smtp.office365.com"), "outlook.com");
+        assert_eq!(from_domain("smtp-mail.outlook.com"), "outlook.com");
+        assert_eq!(from_domain("SMTP.Example.co.kr"), "example.co.kr");
+    }
+
+    #[test]
+    fn from_domain_keeps_the_other_hosts() {
+        assert_eq!(from_domain("mail.com"), "mail.com");
+        assert_eq!(from_domain("relay.example.com"), "relay.example.com");
+        assert_eq!(from_domain("localhost"), "localhost");
+    }
+
+    #[test]
+    fn allowlist_wins_over_blocklist() {
+        let allowlist = vec!["nature".to_string()];
+        let blocklist = vec!["nature".to_string()];
+        assert!(journal_allowed("Nature Energy", &allowlist, &blocklist));
+        assert!(!journal_allowed("Energy Policy", &allowlist, &blocklist));
+    }
+
+    #[test]
+    fn blocklist_ignores_the_case() {
+        let blocklist = vec!["predatory".to_string()];
+        assert!(!journal_allowed("PREDATORY Journal", &[], &blocklist));
+        assert!(journal_allowed("Energy Policy", &[], &blocklist));
+        assert!(journal_allowed("", &[], &[]));
+    }
+}
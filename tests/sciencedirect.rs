@@ -0,0 +1,163 @@
+//! Parses the captured ScienceDirect result pages, served from a local
+//! server, to catch the changes of the page layout.
+//!
+//! It launches Chrome, so it is left out of a plain "cargo test":
+//! ```text
+//! cargo test --test sciencedirect -- --ignored
+//! ```
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::thread;
+
+use tiny_http::{Header, Response, Server};
+
+use linkdrive_rs::{set_settings_path, Crawler, MatchLocation, Paper};
+
+const FIXTURE: &str = include_str!("fixtures/sciencedirect_results.html");
+const EMPTY_FIXTURE: &str = include_str!("fixtures/sciencedirect_empty.html");
+
+/// The keyword that is served the page without results.
+const EMPTY_KEYWORD: &str = "no such paper";
+
+/// The keyword that is served the fixture with its results rendered late.
+const LATE_KEYWORD: &str = "late supply chain";
+
+/// Empties the result list as the page is parsed and renders it again a
+/// moment later, as ScienceDirect does with a slow search.
+const LATE_SCRIPT: &str = r#"<script>
+  const list = document.querySelector('#srp-results-list');
+  const items = list.innerHTML;
+  list.innerHTML = '';
+  setTimeout(() => { list.innerHTML = items; }, 500);
+</script>
+"#;
+
+/// Serves the empty page for [EMPTY_KEYWORD], the late fixture for
+/// [LATE_KEYWORD], the fixture for every other search, and nothing else.
+fn serve_fixture() -> u16 {
+    let server = Server::http("127.0.0.1:0").unwrap();
+    let port = server.server_addr().to_ip().unwrap().port();
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = if request.url().starts_with("/search?") {
+                let header =
+                    Header::from_bytes("Content-Type", "text/html; charset=utf-8").unwrap();
+                let fixture = if request.url().contains("qs=no%20such%20paper") {
+                    EMPTY_FIXTURE.to_string()
+                } else if request.url().contains("qs=late%20supply%20chain") {
+                    FIXTURE.replace("</body>", &format!("{}</body>", LATE_SCRIPT))
+                } else {
+                    FIXTURE.to_string()
+                };
+                Response::from_string(fixture).with_header(header)
+            } else {
+                Response::from_string("Not Found").with_status_code(404)
+            };
+            let _ = request.respond(response);
+        }
+    });
+    port
+}
+
+/// Writes the settings of the test into a folder of its own.
+fn write_settings(base_url: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("linkdrive-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let settings = format!(
+        r#"[default]
+keyword = ["supply chain"]
+email = "test@example.com"
+weekday = "Sun"
+time = "08:30"
+base_url = "{}"
+output_path = "{}"
+element_timeout_ms = 2000
+crawl_delay_ms = 0
+results_per_page = 25
+
+[profile]
+id = "test"
+password = "test"
+"#,
+        base_url,
+        dir.join("Papers.csv").display()
+    );
+    let settings_path = dir.join("Settings.toml");
+    fs::write(&settings_path, settings).unwrap();
+    settings_path
+}
+
+/// Starts the server and points the settings to it, once for all tests,
+/// as the settings path can only be set once.
+fn base_url() -> &'static str {
+    static BASE_URL: OnceLock<String> = OnceLock::new();
+    BASE_URL.get_or_init(|| {
+        let base_url = format!("http://127.0.0.1:{}/", serve_fixture());
+        set_settings_path(write_settings(&base_url));
+        base_url
+    })
+}
+
+#[test]
+#[ignore = "launches Chrome"]
+fn parses_the_captured_result_page() {
+    let base_url = base_url();
+
+    let mut papers: Vec<Paper> = Crawler::search_once(&["supply chain".to_string()]).unwrap();
+    papers.sort_by(|a, b| a.href.cmp(&b.href));
+    assert_eq!(papers.len(), 2);
+
+    let chapter = &papers[0];
+    assert_eq!(chapter.keyword, "supply chain");
+    assert_eq!(chapter.title, "Digital twins of the supply chain");
+    assert_eq!(
+        chapter.href,
+        format!("{}science/article/pii/B9780128234567000012", base_url)
+    );
+    assert_eq!(chapter.journal, "");
+    assert_eq!(chapter.authors, vec!["Alex Kim"]);
+    assert_eq!(chapter.published.as_deref(), Some("2023"));
+    assert_eq!(chapter.doi, None);
+    assert_eq!(chapter.abstract_text, None);
+    assert_eq!(chapter.match_location, MatchLocation::Title);
+
+    let article = &papers[1];
+    assert_eq!(article.keyword, "supply chain");
+    assert_eq!(article.title, "Supply chain resilience after the pandemic");
+    assert_eq!(
+        article.href,
+        format!("{}science/article/pii/S0925527323001234", base_url)
+    );
+    assert_eq!(
+        article.journal,
+        "International Journal of Production Economics"
+    );
+    assert_eq!(article.authors, vec!["Jane Doe", "John Smith"]);
+    assert_eq!(article.published.as_deref(), Some("June 2023"));
+    assert_eq!(article.doi.as_deref(), Some("10.1016/j.ijpe.2023.108912"));
+    assert_eq!(
+        article.abstract_text.as_deref(),
+        Some("Supply chains have been disrupted by the pandemic.")
+    );
+}
+
+#[test]
+#[ignore = "launches Chrome"]
+fn empty_result_page_finds_nothing() {
+    base_url();
+
+    let papers = Crawler::search_once(&[EMPTY_KEYWORD.to_string()]).unwrap();
+    assert!(papers.is_empty());
+}
+
+#[test]
+#[ignore = "launches Chrome"]
+fn late_result_list_is_waited_for() {
+    base_url();
+
+    let papers = Crawler::search_once(&[LATE_KEYWORD.to_string()]).unwrap();
+    assert_eq!(papers.len(), 2);
+    assert!(papers.iter().all(|paper| paper.keyword == LATE_KEYWORD));
+}